@@ -1,11 +1,99 @@
 use crate::colors;
+use crate::env;
+use crate::glyphs;
+use crate::terminal;
+use crate::verbose;
+
+pub enum Command {
+    Prompt,
+    InvalidateCache,
+    Daemon,
+    Explain,
+    CheckConfig,
+    Init(InitOptions),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InitShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+pub struct InitOptions {
+    pub shell: InitShell,
+    pub transient: bool,
+    pub semantic_marks: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    Prompt,
+    Json,
+}
+
+// which part of the prompt to draw: the full prompt, or the main prompt and
+// the right side prompt (for zsh's RPROMPT or fish_right_prompt) separately
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Side {
+    Full,
+    Left,
+    Right,
+}
+
+// which of the shell's prompts to draw: the main prompt (PS1), the prompt
+// for continuation lines of a multi-line command (PS2), or the prompt for
+// the select builtin (PS3)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    Main,
+    Continuation,
+    Select,
+}
 
 pub struct CommandLineOptions {
+    pub command: Command,
     pub shell: colors::ShellType,
     pub error_code: u8,
+    pub vcs_age: bool,
+    pub vcs_stale_after: std::time::Duration,
+    pub background_fetch: Option<std::time::Duration>,
+    pub cache: bool,
+    pub use_daemon: bool,
+    pub vcs_timeout: std::time::Duration,
+    pub power_timeout: std::time::Duration,
+    pub profile: Option<verbose::ProfileFormat>,
+    pub format: OutputFormat,
+    pub side: Side,
+    pub transient: bool,
+    pub kind: Kind,
+    pub semantic_marks: bool,
+    pub report_cwd: bool,
+    pub title: Option<String>,
+    pub hyperlinks: bool,
+    pub branch_url: Option<String>,
+    pub bright_bold: bool,
+    pub theme: colors::Theme,
+    pub auto_colors: bool,
+    pub color: terminal::ColorMode,
+    pub ansi: bool,
+    pub glyphs: glyphs::GlyphSet,
+    pub env: env::Env,
 }
 
 pub fn parse() -> CommandLineOptions {
+    parse_from(std::env::args_os(), env::Env::current())
+        .unwrap_or_else(|e| e.exit())
+}
+
+pub fn parse_from<I, T>(
+    args: I,
+    env: env::Env,
+) -> Result<CommandLineOptions, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
     let matches = clap::Command::new("fancy-prompt")
         .about("Prints a fancy prompt")
         .author(clap::crate_authors!())
@@ -18,13 +106,302 @@ pub fn parse() -> CommandLineOptions {
                     "Produces escape sequence wrappers for the given shell",
                 ),
         )
+        .arg(
+            clap::Arg::new("vcs-age")
+                .long("vcs-age")
+                .action(clap::ArgAction::SetTrue)
+                .help(
+                    "Shows the age of the current commit and of the last fetch",
+                ),
+        )
+        .arg(
+            clap::Arg::new("vcs-stale-after")
+                .long("vcs-stale-after")
+                .value_name("HOURS")
+                // so that it still fits in a duration once it is in seconds
+                .value_parser(
+                    clap::value_parser!(u64).range(..=u64::from(u32::MAX)),
+                )
+                .default_value("168")
+                .help("Highlights the last fetch once it is older than this"),
+        )
+        .arg(
+            clap::Arg::new("background-fetch")
+                .long("background-fetch")
+                .value_name("MINUTES")
+                // so that it still fits in a duration once it is in seconds
+                .value_parser(
+                    clap::value_parser!(u64).range(..=u64::from(u32::MAX)),
+                )
+                .help(
+                    "Fetches from origin in the background at most once \
+                     every MINUTES minutes per repository",
+                ),
+        )
+        .arg(
+            clap::Arg::new("no-cache")
+                .long("no-cache")
+                .action(clap::ArgAction::SetTrue)
+                .help(
+                    "Always recomputes the repository status, rather than \
+                     reusing it until something in the repository changes",
+                ),
+        )
+        .arg(
+            clap::Arg::new("no-daemon")
+                .long("no-daemon")
+                .action(clap::ArgAction::SetTrue)
+                .help("Renders in-process even if a daemon is running"),
+        )
+        .arg(
+            clap::Arg::new("vcs-timeout")
+                .long("vcs-timeout")
+                .value_name("MS")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("1000")
+                .help("Gives up on collecting VCS information after this"),
+        )
+        .arg(
+            clap::Arg::new("power-timeout")
+                .long("power-timeout")
+                .value_name("MS")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("200")
+                .help("Gives up on collecting battery information after this"),
+        )
+        .arg(
+            clap::Arg::new("profile")
+                .long("profile")
+                .value_name("FORMAT")
+                .value_parser(["text", "trace", "summary"])
+                .help(
+                    "Reports how long each step took on stderr, as text, \
+                     chrome trace events or a json summary",
+                ),
+        )
+        .arg(
+            clap::Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .value_parser(["prompt", "json"])
+                .default_value("prompt")
+                .help(
+                    "Prints the rendered prompt, or the data it would be \
+                     rendered from as json",
+                ),
+        )
+        .arg(
+            clap::Arg::new("side")
+                .long("side")
+                .value_name("SIDE")
+                .value_parser(["full", "left", "right"])
+                .default_value("full")
+                .help(
+                    "Draws the whole prompt, or only the main (left) or \
+                     right side prompt",
+                ),
+        )
+        .arg(
+            clap::Arg::new("transient")
+                .long("transient")
+                .action(clap::ArgAction::SetTrue)
+                .help(
+                    "Draws a minimal one line prompt, to replace the full \
+                     prompt once a command has been entered",
+                ),
+        )
+        .arg(
+            clap::Arg::new("kind")
+                .long("kind")
+                .value_name("KIND")
+                .value_parser(["main", "continuation", "select"])
+                .default_value("main")
+                .help(
+                    "Draws the main prompt, or the prompt for continuation \
+                     lines (PS2) or select (PS3)",
+                ),
+        )
+        .arg(
+            clap::Arg::new("semantic-marks")
+                .long("semantic-marks")
+                .action(clap::ArgAction::SetTrue)
+                .help(
+                    "Emits OSC 133 marks around the prompt, so that the \
+                     terminal can jump between prompts",
+                ),
+        )
+        .arg(
+            clap::Arg::new("report-cwd")
+                .long("report-cwd")
+                .action(clap::ArgAction::SetTrue)
+                .help(
+                    "Reports the current directory to the terminal (OSC 7), \
+                     so that new tabs can open there",
+                ),
+        )
+        .arg(
+            clap::Arg::new("title")
+                .long("title")
+                .value_name("TEMPLATE")
+                .help(
+                    "Sets the window title, replacing {user}, {host}, {path} \
+                     and {vcs} in TEMPLATE",
+                ),
+        )
+        .arg(
+            clap::Arg::new("hyperlinks")
+                .long("hyperlinks")
+                .action(clap::ArgAction::SetTrue)
+                .help(
+                    "Links the path to the current directory, and the VCS \
+                     information to the branch on its forge (OSC 8)",
+                ),
+        )
+        .arg(
+            clap::Arg::new("branch-url")
+                .long("branch-url")
+                .value_name("TEMPLATE")
+                .help(
+                    "Links the VCS information to this URL, replacing \
+                     {host}, {repo} and {branch} in TEMPLATE",
+                ),
+        )
+        .arg(
+            clap::Arg::new("color")
+                .long("color")
+                .value_name("WHEN")
+                .value_parser(["auto", "always", "never"])
+                .default_value("auto")
+                .help(
+                    "Draws the prompt in color always, never, or only if \
+                     there is a terminal and NO_COLOR isn't set",
+                ),
+        )
+        .arg(
+            clap::Arg::new("ansi")
+                .long("ansi")
+                .action(clap::ArgAction::SetTrue)
+                .help(
+                    "Draws colors with built-in ANSI escapes, rather than \
+                     looking them up in terminfo (which is the fallback \
+                     anyway if TERM has no terminfo entry)",
+                ),
+        )
+        .arg(
+            clap::Arg::new("glyphs")
+                .long("glyphs")
+                .value_name("SET")
+                .value_parser(["ascii", "nerd-font"])
+                .default_value("ascii")
+                .help(
+                    "Draws the prompt with plain ASCII, or with powerline \
+                     separators and icons (which need a Nerd Font)",
+                ),
+        )
+        .arg(
+            clap::Arg::new("theme")
+                .long("theme")
+                .value_name("THEME")
+                .value_parser(["dark", "light", "monochrome", "auto"])
+                .help(
+                    "Picks the default colors, for dark or light terminals \
+                     (or asks the terminal which it is), or uses no colors \
+                     [default: $FANCY_PROMPT_THEME, or dark]",
+                ),
+        )
+        .arg(
+            clap::Arg::new("auto-colors")
+                .long("auto-colors")
+                .action(clap::ArgAction::SetTrue)
+                .help(
+                    "Picks a color for each host and user without one, \
+                     based on its name",
+                ),
+        )
+        .arg(
+            clap::Arg::new("no-bright-bold")
+                .long("no-bright-bold")
+                .action(clap::ArgAction::SetTrue)
+                .help("Doesn't also make text in bright colors bold"),
+        )
         .arg(
             clap::Arg::new("error-code")
                 .value_name("ERROR_CODE")
                 .value_parser(clap::value_parser!(u8))
                 .help("The error code of the previously run command"),
         )
-        .get_matches();
+        .subcommand(
+            clap::Command::new("invalidate-cache").about(
+                "Removes the cached status of the current repository",
+            ),
+        )
+        .subcommand(clap::Command::new("explain").about(
+            "Explains how each part of the prompt was decided",
+        ))
+        .subcommand(
+            clap::Command::new("check-config")
+                .long_flag("check-config")
+                .about(
+                    "Reports any problems with FANCY_PROMPT_COLORS, \
+                     FANCY_PROMPT_GLYPHS and FANCY_PROMPT_THEME, and exits \
+                     nonzero if there are any",
+                ),
+        )
+        .subcommand(
+            clap::Command::new("init")
+                .about("Prints the shell integration for the given shell")
+                .arg(
+                    clap::Arg::new("shell")
+                        .value_name("SHELL")
+                        .value_parser(["bash", "zsh", "fish"])
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::new("transient")
+                        .long("transient")
+                        .action(clap::ArgAction::SetTrue)
+                        .help(
+                            "Replaces the prompt of each entered command \
+                             with a minimal one (zsh and fish only)",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("semantic-marks")
+                        .long("semantic-marks")
+                        .action(clap::ArgAction::SetTrue)
+                        .help(
+                            "Marks where prompts, commands and their output \
+                             start and end for the terminal",
+                        ),
+                ),
+        )
+        .subcommand(clap::Command::new("daemon").about(
+            "Serves prompts from a long-running process to speed up rendering",
+        ))
+        .try_get_matches_from(args)?;
+
+    let command = match matches.subcommand() {
+        Some(("invalidate-cache", _)) => Command::InvalidateCache,
+        Some(("daemon", _)) => Command::Daemon,
+        Some(("explain", _)) => Command::Explain,
+        Some(("check-config", _)) => Command::CheckConfig,
+        Some(("init", matches)) => {
+            let shell = match matches
+                .get_one::<String>("shell")
+                .map(|s| s.as_str())
+            {
+                Some("zsh") => InitShell::Zsh,
+                Some("fish") => InitShell::Fish,
+                _ => InitShell::Bash,
+            };
+            Command::Init(InitOptions {
+                shell,
+                transient: matches.get_flag("transient"),
+                semantic_marks: matches.get_flag("semantic-marks"),
+            })
+        }
+        _ => Command::Prompt,
+    };
 
     let shell = matches
         .get_one::<String>("prompt-escape")
@@ -32,6 +409,102 @@ pub fn parse() -> CommandLineOptions {
         .unwrap_or(colors::ShellType::Unknown);
     let error_code =
         matches.get_one::<u8>("error-code").copied().unwrap_or(0);
+    let vcs_age = matches.get_flag("vcs-age");
+    let vcs_stale_after = std::time::Duration::from_secs(
+        matches
+            .get_one::<u64>("vcs-stale-after")
+            .copied()
+            .unwrap_or(168)
+            * 60
+            * 60,
+    );
+    let background_fetch = matches
+        .get_one::<u64>("background-fetch")
+        .map(|minutes| std::time::Duration::from_secs(minutes * 60));
+
+    let cache = !matches.get_flag("no-cache");
+    let use_daemon = !matches.get_flag("no-daemon");
+    let vcs_timeout = std::time::Duration::from_millis(
+        matches
+            .get_one::<u64>("vcs-timeout")
+            .copied()
+            .unwrap_or(1000),
+    );
+    let power_timeout = std::time::Duration::from_millis(
+        matches
+            .get_one::<u64>("power-timeout")
+            .copied()
+            .unwrap_or(200),
+    );
+    let profile = matches
+        .get_one::<String>("profile")
+        .and_then(|s| verbose::ProfileFormat::from_str(s));
+    let format = match matches.get_one::<String>("format").map(|s| s.as_str())
+    {
+        Some("json") => OutputFormat::Json,
+        _ => OutputFormat::Prompt,
+    };
+    let side = match matches.get_one::<String>("side").map(|s| s.as_str()) {
+        Some("left") => Side::Left,
+        Some("right") => Side::Right,
+        _ => Side::Full,
+    };
+    let transient = matches.get_flag("transient");
+    let kind = match matches.get_one::<String>("kind").map(|s| s.as_str()) {
+        Some("continuation") => Kind::Continuation,
+        Some("select") => Kind::Select,
+        _ => Kind::Main,
+    };
+    let semantic_marks = matches.get_flag("semantic-marks");
+    let report_cwd = matches.get_flag("report-cwd");
+    let title = matches.get_one::<String>("title").cloned();
+    let hyperlinks = matches.get_flag("hyperlinks");
+    let branch_url = matches.get_one::<String>("branch-url").cloned();
+    let bright_bold = !matches.get_flag("no-bright-bold");
+    let theme = matches
+        .get_one::<String>("theme")
+        .cloned()
+        .or_else(|| env.var("FANCY_PROMPT_THEME").map(String::from))
+        .and_then(|s| colors::Theme::from_str(&s))
+        .unwrap_or(colors::Theme::Dark);
+    let auto_colors = matches.get_flag("auto-colors");
+    let color = matches
+        .get_one::<String>("color")
+        .and_then(|s| terminal::ColorMode::from_str(s))
+        .unwrap_or(terminal::ColorMode::Auto);
+    let ansi = matches.get_flag("ansi");
+    let glyphs = matches
+        .get_one::<String>("glyphs")
+        .and_then(|s| glyphs::GlyphSet::from_str(s))
+        .unwrap_or(glyphs::GlyphSet::Ascii);
 
-    CommandLineOptions { shell, error_code }
+    Ok(CommandLineOptions {
+        command,
+        shell,
+        error_code,
+        vcs_age,
+        vcs_stale_after,
+        background_fetch,
+        cache,
+        use_daemon,
+        vcs_timeout,
+        power_timeout,
+        profile,
+        format,
+        side,
+        transient,
+        kind,
+        semantic_marks,
+        report_cwd,
+        title,
+        hyperlinks,
+        branch_url,
+        bright_bold,
+        theme,
+        auto_colors,
+        color,
+        ansi,
+        glyphs,
+        env,
+    })
 }
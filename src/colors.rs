@@ -5,12 +5,219 @@ pub enum ShellType {
     Zsh,
 }
 
-type ColorMap = std::collections::HashMap<String, term::color::Color>;
+// either one of the terminal's palette colors (the 16 basic colors, followed
+// by the 6x6x6 color cube and the grayscale ramp) or a 24-bit color
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Color {
+    Palette(term::color::Color),
+    Rgb(u8, u8, u8),
+}
+
+// how a display element is drawn. any of these left unset are left as the
+// terminal's defaults.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+type ColorMap = std::collections::HashMap<String, Style>;
+
+// the default colors, for terminals with dark or light backgrounds, or for
+// using only text attributes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Theme {
+    Dark,
+    Light,
+    Monochrome,
+    // dark or light, depending on what the terminal says its background is
+    Auto,
+}
+
+const DARK_THEME: [(&str, term::color::Color); 14] = [
+    ("user_root", term::color::BRIGHT_RED),
+    ("path_not_writable", term::color::YELLOW),
+    ("path_not_exist", term::color::RED),
+    ("vcs_dirty", term::color::RED),
+    ("vcs_error", term::color::BRIGHT_RED),
+    ("vcs_stale", term::color::YELLOW),
+    ("battery_warn", term::color::YELLOW),
+    ("battery_crit", term::color::RED),
+    ("battery_emerg", term::color::BRIGHT_RED),
+    ("battery_full", term::color::GREEN),
+    ("battery_charging", term::color::GREEN),
+    ("default", term::color::BRIGHT_BLACK),
+    ("error", term::color::RED),
+    // not a display element, just the color to use for keys which aren't
+    // set (so that it can be overridden too)
+    ("unknown", term::color::YELLOW),
+];
+
+// yellow and bright black are hard to read on light backgrounds
+const LIGHT_THEME: [(&str, term::color::Color); 14] = [
+    ("user_root", term::color::RED),
+    ("path_not_writable", term::color::MAGENTA),
+    ("path_not_exist", term::color::RED),
+    ("vcs_dirty", term::color::RED),
+    ("vcs_error", term::color::RED),
+    ("vcs_stale", term::color::MAGENTA),
+    ("battery_warn", term::color::MAGENTA),
+    ("battery_crit", term::color::RED),
+    ("battery_emerg", term::color::RED),
+    ("battery_full", term::color::GREEN),
+    ("battery_charging", term::color::GREEN),
+    ("default", term::color::BLACK),
+    ("error", term::color::RED),
+    ("unknown", term::color::BLUE),
+];
+
+// keys which no theme sets, but which can still be given a color. the
+// delimiters use the host's color and the border uses the default color
+// until they are.
+const UNTHEMED_KEYS: [&str; 10] = [
+    "path_open",
+    "path_close",
+    "separator",
+    "battery_open",
+    "battery_close",
+    "time_open",
+    "time_close",
+    "corner_top",
+    "corner_bottom",
+    "border",
+];
+
+// used for hosts and users without a color of their own (with
+// --auto-colors). red and yellow are left out since they already mean
+// something.
+const DARK_AUTO_COLORS: [term::color::Color; 12] = [
+    term::color::GREEN,
+    term::color::BLUE,
+    term::color::MAGENTA,
+    term::color::CYAN,
+    term::color::BRIGHT_GREEN,
+    term::color::BRIGHT_BLUE,
+    term::color::BRIGHT_MAGENTA,
+    term::color::BRIGHT_CYAN,
+    208,
+    141,
+    75,
+    114,
+];
+const LIGHT_AUTO_COLORS: [term::color::Color; 10] = [
+    term::color::GREEN,
+    term::color::BLUE,
+    term::color::MAGENTA,
+    term::color::CYAN,
+    24,
+    90,
+    30,
+    28,
+    130,
+    54,
+];
+
+const MONOCHROME_THEME: [(&str, &str); 14] = [
+    ("user_root", "bold"),
+    ("path_not_writable", "underline"),
+    ("path_not_exist", "reverse"),
+    ("vcs_dirty", "bold"),
+    ("vcs_error", "reverse"),
+    ("vcs_stale", "underline"),
+    ("battery_warn", "bold"),
+    ("battery_crit", "reverse"),
+    ("battery_emerg", "reverse"),
+    ("battery_full", ""),
+    ("battery_charging", ""),
+    ("default", ""),
+    ("error", "bold"),
+    ("unknown", ""),
+];
+
+// the usual xterm values for the 16 basic colors
+const BASIC_COLORS: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+// where the color for a given key came from, for `fancy-prompt explain`
+#[derive(Debug, Copy, Clone)]
+pub enum ColorSource {
+    Default,
+    Env,
+}
+
+// a host_ or user_ key with a glob ("host_prod-*") or a regex
+// ("host_/^prod-db-[0-9]+$/") instead of a name
+struct Rule {
+    key: String,
+    prefix: &'static str,
+    pattern: regex::Regex,
+    style: Style,
+}
 
 pub struct Colors {
     color_map: ColorMap,
-    unknown_color: term::color::Color,
+    sources: std::collections::HashMap<String, ColorSource>,
+    rules: Vec<Rule>,
+    errors: Vec<String>,
     shell_type: ShellType,
+    truecolor: bool,
+    bright_bold: bool,
+    theme: Theme,
+    auto_colors: bool,
+    num_colors: u32,
+}
+
+impl Style {
+    pub fn fg(color: Color) -> Style {
+        Style {
+            fg: Some(color),
+            ..Style::default()
+        }
+    }
+}
+
+impl Theme {
+    pub fn from_str(theme: &str) -> Option<Self> {
+        match theme {
+            "dark" => Some(Theme::Dark),
+            "light" => Some(Theme::Light),
+            "monochrome" => Some(Theme::Monochrome),
+            "auto" => Some(Theme::Auto),
+            _ => None,
+        }
+    }
+
+    pub fn from_background((r, g, b): (u8, u8, u8)) -> Self {
+        let luma =
+            299 * u32::from(r) + 587 * u32::from(g) + 114 * u32::from(b);
+        if luma > 128 * 1000 {
+            Theme::Light
+        } else {
+            Theme::Dark
+        }
+    }
 }
 
 impl ShellType {
@@ -24,54 +231,409 @@ impl ShellType {
 }
 
 impl Colors {
-    pub fn new(shell_type: ShellType) -> Colors {
-        let mut color_map = std::collections::HashMap::new();
-
-        color_map.insert(String::from("user_root"), term::color::BRIGHT_RED);
-
-        color_map
-            .insert(String::from("path_not_writable"), term::color::YELLOW);
-        color_map.insert(String::from("path_not_exist"), term::color::RED);
-        color_map.insert(String::from("vcs_dirty"), term::color::RED);
-        color_map.insert(String::from("vcs_error"), term::color::BRIGHT_RED);
+    pub fn new(
+        shell_type: ShellType,
+        theme: Theme,
+        bright_bold: bool,
+        auto_colors: bool,
+        num_colors: u32,
+        env: &crate::env::Env,
+    ) -> Colors {
+        let mut color_map = Self::theme_colors(theme);
 
-        color_map.insert(String::from("battery_warn"), term::color::YELLOW);
-        color_map.insert(String::from("battery_crit"), term::color::RED);
-        color_map
-            .insert(String::from("battery_emerg"), term::color::BRIGHT_RED);
-        color_map.insert(String::from("battery_full"), term::color::GREEN);
-        color_map
-            .insert(String::from("battery_charging"), term::color::GREEN);
+        let mut sources = color_map
+            .keys()
+            .map(|name| (name.clone(), ColorSource::Default))
+            .collect();
 
-        color_map.insert(String::from("default"), term::color::BRIGHT_BLACK);
-        color_map.insert(String::from("error"), term::color::RED);
+        let mut rules = vec![];
+        let mut errors = vec![];
+        Self::read_colors_from_env(
+            env,
+            &mut color_map,
+            &mut sources,
+            &mut rules,
+            &mut errors,
+        );
 
-        let unknown_color = term::color::YELLOW;
-
-        Self::read_colors_from_env(&mut color_map);
+        // there's no terminfo capability for this, so everyone uses this
+        let truecolor =
+            matches!(env.var("COLORTERM"), Some("truecolor") | Some("24bit"));
 
         Colors {
             color_map,
-            unknown_color,
+            sources,
+            rules,
+            errors,
             shell_type,
+            truecolor,
+            bright_bold,
+            theme,
+            auto_colors,
+            num_colors,
+        }
+    }
+
+    fn theme_colors(theme: Theme) -> ColorMap {
+        let colors = |theme: &[(&str, term::color::Color)]| {
+            theme
+                .iter()
+                .map(|&(name, color)| {
+                    (String::from(name), Style::fg(Color::Palette(color)))
+                })
+                .collect()
+        };
+        match theme {
+            Theme::Dark | Theme::Auto => colors(&DARK_THEME),
+            Theme::Light => colors(&LIGHT_THEME),
+            Theme::Monochrome => MONOCHROME_THEME
+                .iter()
+                .map(|&(name, attr)| {
+                    let mut style = Style::default();
+                    if !attr.is_empty() {
+                        Self::apply_style_item(&mut style, attr).unwrap();
+                    }
+                    (String::from(name), style)
+                })
+                .collect(),
+        }
+    }
+
+    fn read_colors_from_env(
+        env: &crate::env::Env,
+        color_map: &mut ColorMap,
+        sources: &mut std::collections::HashMap<String, ColorSource>,
+        rules: &mut Vec<Rule>,
+        errors: &mut Vec<String>,
+    ) {
+        if let Some(val) = env.var("FANCY_PROMPT_COLORS") {
+            Self::add_colors(val, color_map, sources, rules, errors);
+        }
+    }
+
+    // bad entries are skipped (and reported), so that a typo doesn't keep
+    // the prompt from being drawn at all
+    fn add_colors(
+        val: &str,
+        color_map: &mut ColorMap,
+        sources: &mut std::collections::HashMap<String, ColorSource>,
+        rules: &mut Vec<Rule>,
+        errors: &mut Vec<String>,
+    ) {
+        for (name, style) in Self::parse_colors(val, errors) {
+            match Self::rule(&name, style) {
+                Ok(Some(rule)) => rules.push(rule),
+                Ok(None) if !Self::is_known_key(&name) => {
+                    errors.push(format!("{}: unknown key", name));
+                }
+                Ok(None) => {
+                    color_map.insert(name.clone(), style);
+                    sources.insert(name, ColorSource::Env);
+                }
+                Err(e) => errors.push(format!("{}: {}", name, e)),
+            }
+        }
+    }
+
+    // a typo ("bordr=red") would otherwise just silently do nothing
+    fn is_known_key(key: &str) -> bool {
+        DARK_THEME.iter().any(|&(name, _)| name == key)
+            || UNTHEMED_KEYS.contains(&key)
+            || ["host_", "user_"].iter().any(|prefix| {
+                key.strip_prefix(prefix)
+                    .is_some_and(|name| !name.is_empty())
+            })
+    }
+
+    fn rule(key: &str, style: Style) -> Result<Option<Rule>, String> {
+        let (prefix, pattern) = if let Some(found) = ["host_", "user_"]
+            .iter()
+            .find_map(|&prefix| Some((prefix, key.strip_prefix(prefix)?)))
+        {
+            found
+        } else {
+            return Ok(None);
+        };
+        let pattern = if let Some(re) = pattern
+            .strip_prefix('/')
+            .and_then(|pattern| pattern.strip_suffix('/'))
+        {
+            String::from(re)
+        } else if pattern.contains(['*', '?', '[']) {
+            glob_to_regex(pattern)
+        } else {
+            return Ok(None);
+        };
+        let pattern = regex::Regex::new(&pattern)
+            .map_err(|e| format!("invalid pattern ({})", e))?;
+        Ok(Some(Rule {
+            key: String::from(key),
+            prefix,
+            pattern,
+            style,
+        }))
+    }
+
+    // the first rule (in the order they were given) which matches
+    fn matching_rule(&self, key: &str) -> Option<&Rule> {
+        self.rules.iter().find(|rule| {
+            key.strip_prefix(rule.prefix)
+                .is_some_and(|name| rule.pattern.is_match(name))
+        })
+    }
+
+    // "error=bold,underline,fg=red,bg=black,user_root=red": each key=value
+    // starts a new entry, and the items after it which aren't key=value
+    // pairs (other than fg= and bg=) add to its style. "\," and "\=" are a
+    // literal "," and "=", for patterns like "host_/^db-[0-9]{1\,3}$/".
+    fn parse_colors(
+        val: &str,
+        errors: &mut Vec<String>,
+    ) -> Vec<(String, Style)> {
+        let mut colors: Vec<(String, Style)> = vec![];
+        // the items after an entry which was skipped belong to it, not to
+        // the entry before it
+        let mut skipping = false;
+        for item in split_unescaped(val, ',') {
+            let pair = find_unescaped(item, '=')
+                .map(|idx| (unescape(&item[..idx]), &item[idx + 1..]));
+            let pair =
+                pair.as_ref().map(|(name, value)| (name.as_str(), *value));
+            match pair {
+                // trailing (or doubled) commas
+                _ if item.is_empty() => {}
+                Some(("", _)) => {
+                    errors.push(format!("{}: missing name", item));
+                    skipping = true;
+                }
+                Some((name, value)) if name != "fg" && name != "bg" => {
+                    let mut style = Style::default();
+                    if let Err(e) = Self::apply_style_item(&mut style, value)
+                    {
+                        errors.push(format!("{}: {}", name, e));
+                        skipping = true;
+                    } else {
+                        colors.push((String::from(name), style));
+                        skipping = false;
+                    }
+                }
+                _ if skipping => {}
+                _ => {
+                    if let Some((name, style)) = colors.last_mut() {
+                        if let Err(e) = Self::apply_style_item(style, item) {
+                            errors.push(format!("{}: {}", name, e));
+                        }
+                    } else {
+                        errors
+                            .push(format!("{}: not a key=value pair", item));
+                    }
+                }
+            }
         }
+        colors
     }
 
-    fn read_colors_from_env(color_map: &mut ColorMap) {
-        if let Ok(val) = std::env::var("FANCY_PROMPT_COLORS") {
-            for mapping in val.split(',') {
-                let parts: Vec<_> = mapping.split('=').collect();
-                let (name, color) = (parts[0], parts[1]);
-                color_map.insert(
-                    String::from(name),
-                    Self::color_from_string(color),
+    fn apply_style_item(style: &mut Style, item: &str) -> Result<(), String> {
+        match item {
+            "bold" => style.bold = true,
+            "dim" => style.dim = true,
+            "italic" => style.italic = true,
+            "underline" => style.underline = true,
+            "reverse" => style.reverse = true,
+            _ => {
+                let (slot, color) = if let Some(color) =
+                    item.strip_prefix("bg=")
+                {
+                    (&mut style.bg, color)
+                } else {
+                    (&mut style.fg, item.strip_prefix("fg=").unwrap_or(item))
+                };
+                *slot = Some(
+                    Self::color_from_string(color)
+                        .ok_or_else(|| format!("unknown color {}", color))?,
                 );
             }
         }
+        Ok(())
+    }
+
+    // whether the key has a color of its own, from the theme or from
+    // FANCY_PROMPT_COLORS
+    pub fn is_set(&self, key: &str) -> bool {
+        self.color_map.contains_key(key)
+    }
+
+    // the FANCY_PROMPT_COLORS entries which were skipped, and why
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    // everything wrong with the color configuration in the environment
+    pub fn check_config(env: &crate::env::Env) -> Vec<String> {
+        let mut errors = vec![];
+        if let Some(theme) = env.var("FANCY_PROMPT_THEME") {
+            if Theme::from_str(theme).is_none() {
+                errors.push(format!(
+                    "FANCY_PROMPT_THEME: unknown theme {}",
+                    theme
+                ));
+            }
+        }
+        let mut entry_errors = vec![];
+        Self::read_colors_from_env(
+            env,
+            &mut ColorMap::new(),
+            &mut std::collections::HashMap::new(),
+            &mut vec![],
+            &mut entry_errors,
+        );
+        errors.extend(
+            entry_errors
+                .iter()
+                .map(|error| format!("FANCY_PROMPT_COLORS: {}", error)),
+        );
+        errors
+    }
+
+    // describes the color that will be used for the given key, and why
+    pub fn describe(&self, color: &str) -> String {
+        match (self.color_map.get(color), self.sources.get(color)) {
+            (Some(style), Some(ColorSource::Env)) => format!(
+                "{} = {} (from FANCY_PROMPT_COLORS)",
+                color,
+                Self::style_name(style)
+            ),
+            (Some(style), _) => {
+                format!("{} = {} (default)", color, Self::style_name(style))
+            }
+            (None, _) => {
+                if let Some(rule) = self.matching_rule(color) {
+                    return format!(
+                        "{} = {} (from FANCY_PROMPT_COLORS, matching {})",
+                        color,
+                        Self::style_name(&rule.style),
+                        rule.key
+                    );
+                }
+                let auto = color
+                    .strip_prefix("host_")
+                    .or_else(|| color.strip_prefix("user_"))
+                    .and_then(|name| self.auto_style(name));
+                if let Some(auto) = auto {
+                    format!(
+                        "{} is not set, using {} (automatic)",
+                        color,
+                        Self::style_name(&auto)
+                    )
+                } else {
+                    format!(
+                        "{} is not set, using {}",
+                        color,
+                        Self::style_name(self.unknown_style())
+                    )
+                }
+            }
+        }
+    }
+
+    // a color picked from a hash of the name, so that it's the same every
+    // time (and on every machine with as many colors)
+    fn auto_style(&self, name: &str) -> Option<Style> {
+        if !self.auto_colors {
+            return None;
+        }
+        let palette: &[term::color::Color] = match self.theme {
+            Theme::Dark | Theme::Auto => &DARK_AUTO_COLORS,
+            Theme::Light => &LIGHT_AUTO_COLORS,
+            Theme::Monochrome => return None,
+        };
+        // the nearest basic color to an extended one can be red or yellow,
+        // which already mean something, so only pick colors that the
+        // terminal can actually draw
+        let palette: Vec<_> = palette
+            .iter()
+            .copied()
+            .filter(|&color| color < self.num_colors)
+            .collect();
+        if palette.is_empty() {
+            return None;
+        }
+        // fnv-1a
+        let hash = name.bytes().fold(0x811c9dc5u32, |hash, b| {
+            (hash ^ u32::from(b)).wrapping_mul(0x01000193)
+        });
+        let color = palette[hash as usize % palette.len()];
+        Some(Style::fg(Color::Palette(color)))
+    }
+
+    // the inverse of parse_colors, for a single entry
+    fn style_name(style: &Style) -> String {
+        let mut items = vec![];
+        for (set, name) in [
+            (style.bold, "bold"),
+            (style.dim, "dim"),
+            (style.italic, "italic"),
+            (style.underline, "underline"),
+            (style.reverse, "reverse"),
+        ] {
+            if set {
+                items.push(String::from(name));
+            }
+        }
+        match (style.fg, style.bg) {
+            (Some(fg), None) if items.is_empty() => {
+                return Self::color_name(fg)
+            }
+            (fg, bg) => {
+                if let Some(fg) = fg {
+                    items.push(format!("fg={}", Self::color_name(fg)));
+                }
+                if let Some(bg) = bg {
+                    items.push(format!("bg={}", Self::color_name(bg)));
+                }
+            }
+        }
+        if items.is_empty() {
+            String::from("plain")
+        } else {
+            items.join(",")
+        }
+    }
+
+    fn unknown_style(&self) -> &Style {
+        &self.color_map["unknown"]
+    }
+
+    fn color_name(color: Color) -> String {
+        let color = match color {
+            Color::Palette(color) => color,
+            Color::Rgb(r, g, b) => {
+                return format!("#{:02x}{:02x}{:02x}", r, g, b)
+            }
+        };
+        match color {
+            term::color::BLACK => String::from("black"),
+            term::color::BLUE => String::from("blue"),
+            term::color::BRIGHT_BLACK => String::from("bright_black"),
+            term::color::BRIGHT_BLUE => String::from("bright_blue"),
+            term::color::BRIGHT_CYAN => String::from("bright_cyan"),
+            term::color::BRIGHT_GREEN => String::from("bright_green"),
+            term::color::BRIGHT_MAGENTA => String::from("bright_magenta"),
+            term::color::BRIGHT_RED => String::from("bright_red"),
+            term::color::BRIGHT_WHITE => String::from("bright_white"),
+            term::color::BRIGHT_YELLOW => String::from("bright_yellow"),
+            term::color::CYAN => String::from("cyan"),
+            term::color::GREEN => String::from("green"),
+            term::color::MAGENTA => String::from("magenta"),
+            term::color::RED => String::from("red"),
+            term::color::WHITE => String::from("white"),
+            term::color::YELLOW => String::from("yellow"),
+            _ => format!("color{}", color),
+        }
     }
 
-    fn color_from_string(color_name: &str) -> term::color::Color {
-        match color_name {
+    fn color_from_string(color_name: &str) -> Option<Color> {
+        let color = match color_name {
             "black" => term::color::BLACK,
             "blue" => term::color::BLUE,
             "bright_black" => term::color::BRIGHT_BLACK,
@@ -88,8 +650,28 @@ impl Colors {
             "red" => term::color::RED,
             "white" => term::color::WHITE,
             "yellow" => term::color::YELLOW,
-            _ => panic!("unknown color {}", color_name),
+            _ => {
+                return Self::color_from_number(color_name)
+                    .or_else(|| Self::color_from_hex(color_name))
+            }
+        };
+        Some(Color::Palette(color))
+    }
+
+    // "color123"
+    fn color_from_number(color_name: &str) -> Option<Color> {
+        let idx: u8 = color_name.strip_prefix("color")?.parse().ok()?;
+        Some(Color::Palette(u32::from(idx)))
+    }
+
+    // "#rrggbb"
+    fn color_from_hex(color_name: &str) -> Option<Color> {
+        let hex = color_name.strip_prefix('#')?;
+        if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
         }
+        let component = |i| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        Some(Color::Rgb(component(0)?, component(2)?, component(4)?))
     }
 
     pub fn print<W: std::io::Write>(
@@ -124,9 +706,9 @@ impl Colors {
         text: &str,
     ) {
         let color = host.and_then(|hostname| {
-            self.color_map.get(&format!("host_{}", hostname))
+            self.identity_style(&format!("host_{}", hostname), hostname)
         });
-        self.print_with_color(t, color, text);
+        self.print_with_color(t, color.as_ref(), text);
     }
 
     pub fn print_user<W: std::io::Write>(
@@ -136,17 +718,46 @@ impl Colors {
         text: &str,
     ) {
         let color = user.and_then(|username| {
-            self.color_map.get(&format!("user_{}", username))
+            self.identity_style(&format!("user_{}", username), username)
         });
-        self.print_with_color(t, color, text);
+        self.print_with_color(t, color.as_ref(), text);
+    }
+
+    fn identity_style(&self, key: &str, name: &str) -> Option<Style> {
+        self.color_map
+            .get(key)
+            .copied()
+            .or_else(|| self.matching_rule(key).map(|rule| rule.style))
+            .or_else(|| self.auto_style(name))
+    }
+
+    // an operating system command, for things like marking the prompt or
+    // setting the window title, which the shell shouldn't count as visible
+    pub fn print_osc<W: std::io::Write>(
+        &self,
+        t: &mut dyn term::Terminal<Output = W>,
+        command: &str,
+    ) {
+        let command = match self.shell_type {
+            ShellType::Bash => command.replace('\\', "\\\\"),
+            ShellType::Zsh => command.replace('%', "%%"),
+            ShellType::Unknown => String::from(command),
+        };
+        self.print_wrapped(t, |t| {
+            write!(t, "\x1b]{}\x07", command).unwrap();
+        })
     }
 
     fn print_with_color<W: std::io::Write>(
         &self,
         t: &mut dyn term::Terminal<Output = W>,
-        color: Option<&term::color::Color>,
+        color: Option<&Style>,
         text: &str,
     ) {
+        // glyphs which the current glyph set doesn't have
+        if text.is_empty() {
+            return;
+        }
         self.print_color(t, color);
         write!(t, "{}", text).unwrap();
         self.print_reset(t);
@@ -156,6 +767,9 @@ impl Colors {
         &self,
         t: &mut dyn term::Terminal<Output = W>,
     ) {
+        if !t.supports_reset() {
+            return;
+        }
         self.print_wrapped(t, |t| {
             t.reset().unwrap();
         })
@@ -164,27 +778,87 @@ impl Colors {
     fn print_color<W: std::io::Write>(
         &self,
         t: &mut dyn term::Terminal<Output = W>,
-        color: Option<&term::color::Color>,
+        style: Option<&Style>,
     ) {
+        // styles which can't be undone would leak into the command line
+        if !t.supports_reset() {
+            return;
+        }
         self.print_wrapped(t, |t| {
-            let real_color = *color.unwrap_or(&self.unknown_color);
-            t.fg(real_color).unwrap();
-            match real_color {
-                term::color::BRIGHT_BLACK
-                | term::color::BRIGHT_BLUE
-                | term::color::BRIGHT_CYAN
-                | term::color::BRIGHT_GREEN
-                | term::color::BRIGHT_MAGENTA
-                | term::color::BRIGHT_RED
-                | term::color::BRIGHT_WHITE
-                | term::color::BRIGHT_YELLOW => {
-                    t.attr(term::Attr::Bold).unwrap()
+            let style = style.unwrap_or_else(|| self.unknown_style());
+            let mut bold = style.bold;
+            if let Some(fg) = style.fg {
+                let idx = self.set_color(t, fg, false);
+                let bright =
+                    term::color::BRIGHT_BLACK..=term::color::BRIGHT_WHITE;
+                if self.bright_bold
+                    && idx.is_some_and(|i| bright.contains(&i))
+                {
+                    bold = true;
+                }
+            }
+            if let Some(bg) = style.bg {
+                self.set_color(t, bg, true);
+            }
+            for (set, attr) in [
+                (bold, term::Attr::Bold),
+                (style.dim, term::Attr::Dim),
+                (style.italic, term::Attr::Italic(true)),
+                (style.underline, term::Attr::Underline(true)),
+                (style.reverse, term::Attr::Reverse),
+            ] {
+                if !set {
+                    continue;
+                }
+                // not every terminal can do everything, but the colors
+                // alone are still better than nothing
+                match t.attr(attr) {
+                    Ok(()) | Err(term::Error::NotSupported) => {}
+                    Err(e) => panic!("{}", e),
                 }
-                _ => {}
             }
         })
     }
 
+    // returns the palette index that was actually used, unless it was drawn
+    // as a 24-bit color
+    fn set_color<W: std::io::Write>(
+        &self,
+        t: &mut dyn term::Terminal<Output = W>,
+        color: Color,
+        background: bool,
+    ) -> Option<term::color::Color> {
+        let idx = match color {
+            Color::Rgb(r, g, b) if self.truecolor => {
+                let layer = if background { 48 } else { 38 };
+                write!(t, "\x1b[{};2;{};{};{}m", layer, r, g, b).unwrap();
+                return None;
+            }
+            Color::Rgb(r, g, b) => rgb_to_palette(r, g, b),
+            Color::Palette(idx) => idx,
+        };
+        let set = |t: &mut dyn term::Terminal<Output = W>, idx| {
+            if background {
+                t.bg(idx)
+            } else {
+                t.fg(idx)
+            }
+        };
+        // terminals without 256 colors get the closest of the basic ones
+        // (and term itself takes care of terminals with only 8)
+        match set(t, idx) {
+            Err(term::Error::ColorOutOfRange) => {
+                let idx = nearest_basic_color(idx);
+                set(t, idx).unwrap();
+                Some(idx)
+            }
+            res => {
+                res.unwrap();
+                Some(idx)
+            }
+        }
+    }
+
     fn print_wrapped<W: std::io::Write, T>(
         &self,
         t: &mut dyn term::Terminal<Output = W>,
@@ -194,10 +868,10 @@ impl Colors {
     {
         match self.shell_type {
             ShellType::Bash => {
-                print!("\\[");
+                write!(t, "\\[").unwrap();
             }
             ShellType::Zsh => {
-                print!("%{{");
+                write!(t, "%{{").unwrap();
             }
             _ => {}
         }
@@ -206,12 +880,374 @@ impl Colors {
 
         match self.shell_type {
             ShellType::Bash => {
-                print!("\\]");
+                write!(t, "\\]").unwrap();
             }
             ShellType::Zsh => {
-                print!("%}}");
+                write!(t, "%}}").unwrap();
             }
             _ => {}
         }
     }
 }
+
+// a backslash keeps the character after it from being a separator
+pub(crate) fn find_unescaped(val: &str, sep: char) -> Option<usize> {
+    let mut escaped = false;
+    val.char_indices()
+        .find(|&(_, c)| {
+            let found = !escaped && c == sep;
+            escaped = !escaped && c == '\\';
+            found
+        })
+        .map(|(idx, _)| idx)
+}
+
+pub(crate) fn split_unescaped(mut val: &str, sep: char) -> Vec<&str> {
+    let mut items = vec![];
+    while let Some(idx) = find_unescaped(val, sep) {
+        items.push(&val[..idx]);
+        val = &val[idx + sep.len_utf8()..];
+    }
+    items.push(val);
+    items
+}
+
+// only "\," and "\=" are escapes, so that regexes can use backslashes as
+// they normally would
+pub(crate) fn unescape(val: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = val.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(c @ (',' | '=')) => unescaped.push(c),
+            Some(c) => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+// "prod-*" becomes "^prod-.*$", and character classes are passed through
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    let mut in_class = false;
+    for c in glob.chars() {
+        match c {
+            '*' if !in_class => re.push_str(".*"),
+            '?' if !in_class => re.push('.'),
+            '[' if !in_class => {
+                in_class = true;
+                re.push('[');
+            }
+            ']' if in_class => {
+                in_class = false;
+                re.push(']');
+            }
+            '!' if in_class && re.ends_with('[') => re.push('^'),
+            c if in_class => re.push(c),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+fn palette_to_rgb(idx: term::color::Color) -> (u8, u8, u8) {
+    match idx {
+        0..=15 => BASIC_COLORS[idx as usize],
+        16..=231 => {
+            let idx = (idx - 16) as usize;
+            (
+                CUBE_LEVELS[idx / 36],
+                CUBE_LEVELS[idx / 6 % 6],
+                CUBE_LEVELS[idx % 6],
+            )
+        }
+        _ => {
+            let level = 8 + 10 * (idx.min(255) - 232) as u8;
+            (level, level, level)
+        }
+    }
+}
+
+fn rgb_to_palette(r: u8, g: u8, b: u8) -> term::color::Color {
+    (16..=255)
+        .min_by_key(|&idx| distance(palette_to_rgb(idx), (r, g, b)))
+        .unwrap()
+}
+
+fn nearest_basic_color(idx: term::color::Color) -> term::color::Color {
+    let rgb = palette_to_rgb(idx);
+    (0..16)
+        .min_by_key(|&basic| distance(palette_to_rgb(basic), rgb))
+        .unwrap()
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (i32::from(x) - i32::from(y)).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_color_from_string() {
+        assert_eq!(
+            Colors::color_from_string("bright_red"),
+            Some(Color::Palette(term::color::BRIGHT_RED))
+        );
+        assert_eq!(
+            Colors::color_from_string("color123"),
+            Some(Color::Palette(123))
+        );
+        assert_eq!(
+            Colors::color_from_string("#ff8000"),
+            Some(Color::Rgb(255, 128, 0))
+        );
+        assert_eq!(Colors::color_from_string("bluee"), None);
+        assert_eq!(Colors::color_name(Color::Palette(123)), "color123");
+        assert_eq!(Colors::color_name(Color::Rgb(255, 128, 0)), "#ff8000");
+    }
+
+    #[test]
+    fn test_parse_colors() {
+        let colors = Colors::parse_colors(
+            "error=bold,underline,fg=red,bg=black,user_root=bright_red,\
+             host_lance=#ff8000,dim",
+            &mut vec![],
+        );
+        assert_eq!(
+            colors,
+            vec![
+                (
+                    String::from("error"),
+                    Style {
+                        fg: Some(Color::Palette(term::color::RED)),
+                        bg: Some(Color::Palette(term::color::BLACK)),
+                        bold: true,
+                        underline: true,
+                        ..Style::default()
+                    }
+                ),
+                (
+                    String::from("user_root"),
+                    Style::fg(Color::Palette(term::color::BRIGHT_RED))
+                ),
+                (
+                    String::from("host_lance"),
+                    Style {
+                        fg: Some(Color::Rgb(255, 128, 0)),
+                        dim: true,
+                        ..Style::default()
+                    }
+                ),
+            ]
+        );
+        assert_eq!(
+            Colors::style_name(&colors[0].1),
+            "bold,underline,fg=red,bg=black"
+        );
+        assert_eq!(Colors::style_name(&colors[1].1), "bright_red");
+        assert_eq!(Colors::style_name(&Style::default()), "plain");
+    }
+
+    #[test]
+    fn test_parse_colors_errors() {
+        let mut errors = vec![];
+        let colors = Colors::parse_colors(
+            "bold,user_doy=bluee,underline,error=red,blink,,=green,\
+             host_lance=yellow,",
+            &mut errors,
+        );
+        assert_eq!(
+            colors,
+            vec![
+                (
+                    String::from("error"),
+                    Style::fg(Color::Palette(term::color::RED))
+                ),
+                (
+                    String::from("host_lance"),
+                    Style::fg(Color::Palette(term::color::YELLOW))
+                ),
+            ]
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "bold: not a key=value pair",
+                "user_doy: unknown color bluee",
+                "error: unknown color blink",
+                "=green: missing name",
+            ]
+        );
+
+        let mut color_map = ColorMap::new();
+        let mut rules = vec![];
+        let mut errors = vec![];
+        Colors::add_colors(
+            "host_/prod-(/=red,host_lance=blue,bordr=red,border=red,host_=red,\
+             vcs_stale=red",
+            &mut color_map,
+            &mut std::collections::HashMap::new(),
+            &mut rules,
+            &mut errors,
+        );
+        assert!(rules.is_empty());
+        assert!(color_map.contains_key("host_lance"));
+        assert!(color_map.contains_key("border"));
+        assert!(color_map.contains_key("vcs_stale"));
+        assert!(!color_map.contains_key("bordr"));
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].starts_with("host_/prod-(/: invalid pattern"));
+        assert_eq!(errors[1], "bordr: unknown key");
+        assert_eq!(errors[2], "host_: unknown key");
+    }
+
+    #[test]
+    fn test_themes() {
+        assert_eq!(Theme::from_background((0, 43, 54)), Theme::Dark);
+        assert_eq!(Theme::from_background((253, 246, 227)), Theme::Light);
+
+        let light = Colors::theme_colors(Theme::Light);
+        assert_eq!(
+            light["default"],
+            Style::fg(Color::Palette(term::color::BLACK))
+        );
+        let monochrome = Colors::theme_colors(Theme::Monochrome);
+        assert!(monochrome.values().all(|style| style.fg.is_none()));
+        assert!(monochrome["error"].bold);
+        assert_eq!(light.len(), monochrome.len());
+    }
+
+    #[test]
+    fn test_auto_colors() {
+        let colors = |theme, auto_colors, num_colors| Colors {
+            color_map: Colors::theme_colors(theme),
+            sources: std::collections::HashMap::new(),
+            rules: vec![],
+            errors: vec![],
+            shell_type: ShellType::Unknown,
+            truecolor: false,
+            bright_bold: true,
+            theme,
+            auto_colors,
+            num_colors,
+        };
+
+        let dark = colors(Theme::Dark, true, 256);
+        assert_eq!(dark.auto_style("lance"), dark.auto_style("lance"));
+        let picked: std::collections::HashSet<_> =
+            ["lance", "hush", "prod-db-17", "prod-db-18", "doy", "root"]
+                .iter()
+                .map(|name| dark.auto_style(name).unwrap().fg)
+                .collect();
+        assert!(picked.len() > 1);
+        assert_eq!(
+            dark.describe("host_lance"),
+            format!(
+                "host_lance is not set, using {} (automatic)",
+                Colors::style_name(&dark.auto_style("lance").unwrap())
+            )
+        );
+
+        assert_eq!(colors(Theme::Dark, false, 256).auto_style("lance"), None);
+        assert_eq!(
+            colors(Theme::Monochrome, true, 256).auto_style("lance"),
+            None
+        );
+
+        // only colors the terminal has, rather than the nearest basic
+        // color to an extended one
+        for (theme, num_colors) in
+            [(Theme::Dark, 16), (Theme::Light, 16), (Theme::Dark, 8)]
+        {
+            let colors = colors(theme, true, num_colors);
+            for name in ["lance", "hush", "prod-db-17", "doy", "root"] {
+                let style = colors.auto_style(name).unwrap();
+                assert!(matches!(
+                    style.fg,
+                    Some(Color::Palette(color)) if color < num_colors
+                ));
+            }
+        }
+        assert_eq!(colors(Theme::Dark, true, 0).auto_style("lance"), None);
+    }
+
+    #[test]
+    fn test_rules() {
+        let mut color_map = Colors::theme_colors(Theme::Dark);
+        let mut sources = std::collections::HashMap::new();
+        let mut rules = vec![];
+        Colors::add_colors(
+            "host_prod-db-17=green,host_prod-*=red,host_prod-db-*=blue,\
+             host_/^staging-[0-9]+$/=yellow,user_[!d]*=magenta,\
+             host_/^db-\\d{1\\,3}$/=cyan,user_/^d\\=y$/=white",
+            &mut color_map,
+            &mut sources,
+            &mut rules,
+            &mut vec![],
+        );
+        let colors = Colors {
+            color_map,
+            sources,
+            rules,
+            errors: vec![],
+            shell_type: ShellType::Unknown,
+            truecolor: false,
+            bright_bold: true,
+            theme: Theme::Dark,
+            auto_colors: false,
+            num_colors: 256,
+        };
+
+        let fg = |key: &str, name| {
+            colors.identity_style(key, name).and_then(|style| style.fg)
+        };
+        let palette = |color| Some(Color::Palette(color));
+        assert_eq!(
+            fg("host_prod-db-17", "prod-db-17"),
+            palette(term::color::GREEN)
+        );
+        // rules are checked in order, so the more specific one never wins
+        assert_eq!(
+            fg("host_prod-db-18", "prod-db-18"),
+            palette(term::color::RED)
+        );
+        assert_eq!(
+            fg("host_staging-3", "staging-3"),
+            palette(term::color::YELLOW)
+        );
+        assert_eq!(fg("host_staging-x", "staging-x"), None);
+        assert_eq!(fg("host_xprod-1", "xprod-1"), None);
+        assert_eq!(fg("user_alice", "alice"), palette(term::color::MAGENTA));
+        assert_eq!(fg("user_doy", "doy"), None);
+        assert_eq!(fg("host_db-123", "db-123"), palette(term::color::CYAN));
+        assert_eq!(fg("host_db-1234", "db-1234"), None);
+        assert_eq!(fg("user_d=y", "d=y"), palette(term::color::WHITE));
+        assert_eq!(
+            colors.describe("host_prod-db-18"),
+            "host_prod-db-18 = red (from FANCY_PROMPT_COLORS, matching \
+             host_prod-*)"
+        );
+    }
+
+    #[test]
+    fn test_palette_fallback() {
+        assert_eq!(rgb_to_palette(255, 0, 0), 196);
+        assert_eq!(rgb_to_palette(0, 0, 0), 16);
+        assert_eq!(rgb_to_palette(128, 128, 128), 244);
+        assert_eq!(nearest_basic_color(196), term::color::BRIGHT_RED);
+        assert_eq!(nearest_basic_color(16), term::color::BLACK);
+        assert_eq!(nearest_basic_color(term::color::RED), term::color::RED);
+    }
+}
@@ -0,0 +1,318 @@
+use std::io::{Read as _, Write as _};
+use std::os::unix::ffi::{OsStrExt as _, OsStringExt as _};
+
+use crate::args;
+use crate::data;
+use crate::dirs;
+use crate::env;
+use crate::prompt;
+use crate::vcs;
+
+// everything that rendering depends on which differs between the client and
+// the daemon process
+struct Request {
+    cwd: std::ffi::OsString,
+    terminal_cols: Option<usize>,
+    // the daemon has threads, so it can't work this out itself
+    utc_offset: time::UtcOffset,
+    args: Vec<std::ffi::OsString>,
+    env: Vec<(std::ffi::OsString, std::ffi::OsString)>,
+}
+
+impl Request {
+    fn current() -> Option<Request> {
+        Some(Request {
+            cwd: std::env::current_dir().ok()?.into_os_string(),
+            terminal_cols: data::terminal_cols(),
+            utc_offset: env::local_offset(),
+            args: std::env::args_os().collect(),
+            env: std::env::vars_os().collect(),
+        })
+    }
+
+    // fields are NUL terminated: the cwd, the terminal width (empty if
+    // unknown), the local utc offset in seconds, the number of arguments,
+    // the arguments themselves, and then the environment as KEY=VALUE pairs
+    // until the end of the request
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        let mut field = |bytes: &[u8]| {
+            buf.extend_from_slice(bytes);
+            buf.push(0);
+        };
+        field(self.cwd.as_bytes());
+        field(
+            self.terminal_cols
+                .map(|cols| cols.to_string())
+                .unwrap_or_default()
+                .as_bytes(),
+        );
+        field(self.utc_offset.whole_seconds().to_string().as_bytes());
+        field(self.args.len().to_string().as_bytes());
+        for arg in &self.args {
+            field(arg.as_bytes());
+        }
+        for (key, value) in &self.env {
+            field(&[key.as_bytes(), b"=", value.as_bytes()].concat());
+        }
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Request> {
+        let mut fields = buf
+            .strip_suffix(b"\0")?
+            .split(|&b| b == 0)
+            .map(|field| std::ffi::OsString::from_vec(field.to_vec()));
+
+        let cwd = fields.next()?;
+        let terminal_cols = fields.next()?;
+        let terminal_cols = if terminal_cols.is_empty() {
+            None
+        } else {
+            Some(terminal_cols.to_str()?.parse().ok()?)
+        };
+        let utc_offset = time::UtcOffset::from_whole_seconds(
+            fields.next()?.to_str()?.parse().ok()?,
+        )
+        .ok()?;
+        let argc: usize = fields.next()?.to_str()?.parse().ok()?;
+        let mut args = vec![];
+        for _ in 0..argc {
+            args.push(fields.next()?);
+        }
+        let mut env = vec![];
+        for pair in fields {
+            let pair = pair.into_vec();
+            let idx = pair.iter().position(|&b| b == b'=')?;
+            env.push((
+                std::ffi::OsString::from_vec(pair[..idx].to_vec()),
+                std::ffi::OsString::from_vec(pair[idx + 1..].to_vec()),
+            ));
+        }
+
+        Some(Request {
+            cwd,
+            terminal_cols,
+            utc_offset,
+            args,
+            env,
+        })
+    }
+}
+
+fn socket_path() -> Option<std::path::PathBuf> {
+    dirs::runtime_dir().map(|dir| dir.join("daemon.sock"))
+}
+
+// returns None if there is no daemon running (or if it failed in any way),
+// in which case the caller should render the prompt itself
+pub fn request() -> Option<Vec<u8>> {
+    let mut socket =
+        std::os::unix::net::UnixStream::connect(socket_path()?).ok()?;
+    let timeout = Some(std::time::Duration::from_secs(1));
+    socket.set_read_timeout(timeout).ok()?;
+    socket.set_write_timeout(timeout).ok()?;
+
+    socket.write_all(&Request::current()?.encode()).ok()?;
+    socket.shutdown(std::net::Shutdown::Write).ok()?;
+
+    let mut response = vec![];
+    socket.read_to_end(&mut response).ok()?;
+    response.strip_prefix(b"ok\0").map(<[u8]>::to_vec)
+}
+
+pub fn run() -> std::io::Result<()> {
+    let path = socket_path().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "XDG_RUNTIME_DIR is not set",
+        )
+    })?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            "a daemon is already running",
+        ));
+    }
+    // left over from a daemon that didn't exit cleanly
+    let _ = std::fs::remove_file(&path);
+
+    let listener = std::os::unix::net::UnixListener::bind(&path)?;
+    // the watcher is shared with the threads that look up the repository,
+    // so that a slow repository can be given up on like it would be
+    // outside of the daemon. a lookup that is still running when the next
+    // request comes in just makes that request wait for the lock.
+    let watcher =
+        std::sync::Arc::new(std::sync::Mutex::new(vcs::Watcher::new()?));
+
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| handle(&watcher, stream));
+        if let Err(e) = result {
+            eprintln!("fancy-prompt daemon: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+fn handle(
+    watcher: &std::sync::Arc<std::sync::Mutex<vcs::Watcher>>,
+    mut stream: std::os::unix::net::UnixStream,
+) -> std::io::Result<()> {
+    start_talking_about_time!("request");
+
+    check_peer(&stream)?;
+    talk_about_time!("checking peer");
+
+    let timeout = Some(std::time::Duration::from_secs(1));
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+
+    let mut buf = vec![];
+    stream.read_to_end(&mut buf)?;
+    let request = Request::decode(&buf).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "bad request")
+    })?;
+    talk_about_time!("reading request");
+
+    let env = env::Env::new(
+        request.env,
+        Some(std::path::PathBuf::from(request.cwd)),
+        request.utc_offset,
+    );
+    let opts = args::parse_from(&request.args, env).map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string())
+    })?;
+    let format = opts.format;
+    talk_about_time!("parsing args");
+
+    // a panic while rendering (a terminal that is too small, say) shouldn't
+    // take the daemon down - the client will fall back to rendering it
+    // itself, and report the error there
+    let output =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut data = data::collect_with(opts, |opts, deadline| {
+                let watcher = std::sync::Arc::clone(watcher);
+                data::Pending::spawn(deadline, move || {
+                    // a panic in an earlier lookup doesn't leave the
+                    // watcher in any worse state than a missed event would
+                    let mut watcher = watcher
+                        .lock()
+                        .unwrap_or_else(std::sync::PoisonError::into_inner);
+                    watcher.detect(&opts)
+                })
+            });
+            data.terminal_cols = request.terminal_cols;
+            let mut output = vec![];
+            prompt::render(format, data, &mut output).map(|()| output)
+        }));
+    talk_about_time!("rendering");
+
+    if let Ok(Ok(output)) = output {
+        stream.write_all(b"ok\0")?;
+        stream.write_all(&output)?;
+    }
+    talk_about_time!("writing response");
+    stop_talking_about_time!();
+
+    Ok(())
+}
+
+// the user and whether they are root are the daemon's own, so it can only
+// draw prompts for its own user. a root shell from su (which may keep
+// XDG_RUNTIME_DIR) renders its prompt itself instead.
+fn check_peer(
+    stream: &std::os::unix::net::UnixStream,
+) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd as _;
+
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if cred.uid != unsafe { libc::getuid() } {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("refusing a client running as uid {}", cred.uid),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_request_roundtrip() {
+        let request = Request {
+            cwd: std::ffi::OsString::from("/home/doy"),
+            terminal_cols: Some(80),
+            utc_offset: time::UtcOffset::from_hms(-4, -30, 0).unwrap(),
+            args: vec![
+                std::ffi::OsString::from("fancy-prompt"),
+                std::ffi::OsString::from("--prompt-escape"),
+                std::ffi::OsString::from("zsh"),
+                std::ffi::OsString::from("1"),
+            ],
+            env: vec![
+                (
+                    std::ffi::OsString::from("HOME"),
+                    std::ffi::OsString::from("/home/doy"),
+                ),
+                (
+                    std::ffi::OsString::from("FANCY_PROMPT_COLORS"),
+                    std::ffi::OsString::from("user_doy=blue,host_lance=red"),
+                ),
+                (
+                    std::ffi::OsString::from("EMPTY"),
+                    std::ffi::OsString::new(),
+                ),
+            ],
+        };
+        let decoded = Request::decode(&request.encode()).unwrap();
+        assert_eq!(decoded.cwd, request.cwd);
+        assert_eq!(decoded.terminal_cols, request.terminal_cols);
+        assert_eq!(decoded.utc_offset, request.utc_offset);
+        assert_eq!(decoded.args, request.args);
+        assert_eq!(decoded.env, request.env);
+
+        let request = Request {
+            terminal_cols: None,
+            args: vec![],
+            env: vec![],
+            ..request
+        };
+        let decoded = Request::decode(&request.encode()).unwrap();
+        assert_eq!(decoded.terminal_cols, None);
+        assert!(decoded.args.is_empty());
+        assert!(decoded.env.is_empty());
+
+        assert!(Request::decode(b"").is_none());
+        assert!(Request::decode(b"/\x0080\x000\x005\x00a\x00").is_none());
+    }
+
+    #[test]
+    fn test_check_peer() {
+        let (client, _server) =
+            std::os::unix::net::UnixStream::pair().unwrap();
+        assert!(check_peer(&client).is_ok());
+    }
+}
@@ -2,51 +2,251 @@ use std::os::fd::AsRawFd as _;
 
 use crate::args;
 use crate::colors;
+use crate::env;
+use crate::glyphs;
 use crate::power;
+use crate::terminal;
 use crate::vcs;
 
 pub struct PromptData {
     pub shell: colors::ShellType,
+    pub side: args::Side,
+    pub transient: bool,
+    pub kind: args::Kind,
+    pub semantic_marks: bool,
+    pub report_cwd: bool,
+    pub title: Option<String>,
+    pub hyperlinks: bool,
+    pub branch_url: Option<String>,
+    pub bright_bold: bool,
+    pub theme: colors::Theme,
+    pub auto_colors: bool,
+    pub color: terminal::ColorMode,
+    pub ansi: bool,
+    pub glyphs: glyphs::GlyphSet,
     pub error_code: u8,
+    pub vcs_age: bool,
+    pub vcs_stale_after: std::time::Duration,
     pub hostname: Option<String>,
+    // hostname is cut at the first '.' for display, but file:// urls need
+    // the whole name
+    pub full_hostname: Option<String>,
     pub terminal_cols: Option<usize>,
     pub pwd: Option<std::path::PathBuf>,
     pub home: Option<std::path::PathBuf>,
     pub user: Option<String>,
     pub is_root: bool,
     pub time: time::OffsetDateTime,
-    pub power_info: power::PowerInfo,
-    pub vcs_info: Option<Box<dyn vcs::VcsInfo>>,
+    pub power_info: Collected<power::PowerInfo>,
+    pub vcs_info: Collected<Option<Box<dyn vcs::VcsInfo>>>,
+    pub env: env::Env,
 }
 
-pub fn collect(opts: args::CommandLineOptions) -> PromptData {
+// a plain copy of everything the prompt is rendered from, for --format json
+#[derive(Debug, serde::Serialize)]
+pub struct Snapshot {
+    pub user: Option<String>,
+    pub is_root: bool,
+    pub hostname: Option<String>,
+    // paths aren't necessarily unicode, but json strings have to be
+    pub pwd: Option<String>,
+    pub home: Option<String>,
+    pub terminal_cols: Option<usize>,
+    pub error_code: u8,
+    pub time: Option<String>,
+    pub power: Option<power::Snapshot>,
+    pub vcs: Option<vcs::Snapshot>,
+    // the slow sources which missed their deadline (and so are null above)
+    pub timed_out: Vec<&'static str>,
+}
+
+impl PromptData {
+    pub fn snapshot(&self) -> Snapshot {
+        let mut timed_out = vec![];
+        if self.power_info.is_timed_out() {
+            timed_out.push("power");
+        }
+        if self.vcs_info.is_timed_out() {
+            timed_out.push("vcs");
+        }
+        Snapshot {
+            user: self.user.clone(),
+            is_root: self.is_root,
+            hostname: self.hostname.clone(),
+            pwd: self.pwd.as_deref().map(lossy),
+            home: self.home.as_deref().map(lossy),
+            terminal_cols: self.terminal_cols,
+            error_code: self.error_code,
+            time: self
+                .time
+                .format(&time::format_description::well_known::Rfc3339)
+                .ok(),
+            power: self.power_info.ready().map(power::PowerInfo::snapshot),
+            vcs: self
+                .vcs_info
+                .ready()
+                .and_then(|vcs_info| vcs_info.as_deref())
+                .map(vcs::snapshot),
+            timed_out,
+        }
+    }
+}
+
+// slow sources are collected on their own threads, and are rendered as a
+// placeholder if they don't finish before their deadline
+pub enum Collected<T> {
+    Ready(T),
+    TimedOut,
+}
+
+impl<T> Collected<T> {
+    pub fn ready(&self) -> Option<&T> {
+        match self {
+            Collected::Ready(value) => Some(value),
+            Collected::TimedOut => None,
+        }
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        matches!(self, Collected::TimedOut)
+    }
+}
+
+pub struct Pending<T> {
+    rx: std::sync::mpsc::Receiver<T>,
+    deadline: std::time::Instant,
+}
+
+impl<T: Send + 'static> Pending<T> {
+    pub fn spawn<F>(deadline: std::time::Instant, f: F) -> Pending<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (tx, rx) = std::sync::mpsc::channel();
+        // if we give up waiting, the thread is left to finish (or not) on
+        // its own, and the result is just dropped
+        std::thread::spawn(move || {
+            let _ = tx.send(f());
+        });
+        Pending { rx, deadline }
+    }
+
+    pub fn ready(value: T) -> Pending<T> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let _ = tx.send(value);
+        Pending {
+            rx,
+            deadline: std::time::Instant::now(),
+        }
+    }
+
+    fn wait(self) -> Collected<T> {
+        let timeout = self
+            .deadline
+            .saturating_duration_since(std::time::Instant::now());
+        self.rx
+            .recv_timeout(timeout)
+            .map(Collected::Ready)
+            .unwrap_or(Collected::TimedOut)
+    }
+}
+
+pub fn collect(mut opts: args::CommandLineOptions) -> PromptData {
+    // this needs to talk to the terminal that the prompt is being drawn
+    // in, so it can't happen in the daemon
+    if opts.theme == colors::Theme::Auto {
+        opts.theme = theme();
+    }
+    collect_with(opts, |opts, deadline| {
+        Pending::spawn(deadline, move || vcs::detect(&opts))
+    })
+}
+
+pub fn collect_with<F>(
+    opts: args::CommandLineOptions,
+    detect_vcs: F,
+) -> PromptData
+where
+    F: FnOnce(
+        vcs::DetectOptions,
+        std::time::Instant,
+    ) -> Pending<Option<Box<dyn vcs::VcsInfo>>>,
+{
     start_talking_about_time!("collecting data");
 
-    let hostname = hostname();
+    let time = time(&opts.env);
+    talk_about_time!("time");
+
+    let start = std::time::Instant::now();
+    let power_info = if needs_power(&opts) {
+        Pending::spawn(start + opts.power_timeout, power_info)
+    } else {
+        Pending::ready(power::PowerInfo::default())
+    };
+    let vcs_info = if needs_vcs(&opts) {
+        detect_vcs(
+            vcs::DetectOptions {
+                background_fetch: opts.background_fetch,
+                cache: opts.cache,
+                env: opts.env.clone(),
+            },
+            start + opts.vcs_timeout,
+        )
+    } else {
+        Pending::ready(None)
+    };
+    talk_about_time!("starting slow sources");
+
+    let full_hostname = full_hostname();
+    let hostname = full_hostname.as_deref().map(short_hostname);
     talk_about_time!("hostname");
     let terminal_cols = terminal_cols();
     talk_about_time!("terminal_cols");
-    let pwd = pwd();
+    let pwd = pwd(&opts.env);
     talk_about_time!("pwd");
-    let home = home();
+    let home = home(&opts.env);
     talk_about_time!("home");
     let user = user();
     talk_about_time!("user");
     let is_root = is_root();
     talk_about_time!("is_root");
-    let time = time();
-    talk_about_time!("time");
-    let power_info = power_info();
-    talk_about_time!("power_info");
-    let vcs_info = vcs_info();
-    talk_about_time!("vcs_info");
+
+    let power_info = power_info.wait();
+    if power_info.is_timed_out() {
+        talk_about_time!("power_info (timed out)");
+    } else {
+        talk_about_time!("power_info");
+    }
+    let vcs_info = vcs_info.wait();
+    if vcs_info.is_timed_out() {
+        talk_about_time!("vcs_info (timed out)");
+    } else {
+        talk_about_time!("vcs_info");
+    }
 
     stop_talking_about_time!();
 
     PromptData {
         shell: opts.shell,
+        side: opts.side,
+        transient: opts.transient,
+        kind: opts.kind,
+        semantic_marks: opts.semantic_marks,
+        report_cwd: opts.report_cwd,
+        title: opts.title,
+        hyperlinks: opts.hyperlinks,
+        branch_url: opts.branch_url,
+        bright_bold: opts.bright_bold,
+        theme: opts.theme,
+        auto_colors: opts.auto_colors,
+        color: opts.color,
+        ansi: opts.ansi,
+        glyphs: opts.glyphs,
         error_code: opts.error_code,
+        vcs_age: opts.vcs_age,
+        vcs_stale_after: opts.vcs_stale_after,
         hostname,
+        full_hostname,
         terminal_cols,
         pwd,
         home,
@@ -55,22 +255,33 @@ pub fn collect(opts: args::CommandLineOptions) -> PromptData {
         time,
         power_info,
         vcs_info,
+        env: opts.env,
     }
 }
 
-fn hostname() -> Option<String> {
+fn theme() -> colors::Theme {
+    crate::sys::terminal_background(std::time::Duration::from_millis(100))
+        .map(colors::Theme::from_background)
+        .unwrap_or(colors::Theme::Dark)
+}
+
+fn full_hostname() -> Option<String> {
     if let Ok(name) = hostname::get() {
-        let mut name = name.into_string().unwrap();
-        if let Some(idx) = name.find('.') {
-            name.truncate(idx);
-        }
-        Some(name)
+        Some(name.into_string().unwrap())
     } else {
         None
     }
 }
 
-fn terminal_cols() -> Option<usize> {
+fn short_hostname(name: &str) -> String {
+    let mut name = String::from(name);
+    if let Some(idx) = name.find('.') {
+        name.truncate(idx);
+    }
+    name
+}
+
+pub fn terminal_cols() -> Option<usize> {
     for fd in [
         std::io::stdout().as_raw_fd(),
         std::io::stderr().as_raw_fd(),
@@ -83,12 +294,16 @@ fn terminal_cols() -> Option<usize> {
     None
 }
 
-fn pwd() -> Option<std::path::PathBuf> {
-    std::env::var("PWD").map(std::path::PathBuf::from).ok()
+fn pwd(env: &env::Env) -> Option<std::path::PathBuf> {
+    env.var_os("PWD").map(std::path::PathBuf::from)
+}
+
+fn home(env: &env::Env) -> Option<std::path::PathBuf> {
+    env.var_os("HOME").map(std::path::PathBuf::from)
 }
 
-fn home() -> Option<std::path::PathBuf> {
-    std::env::var("HOME").map(std::path::PathBuf::from).ok()
+fn lossy(path: &std::path::Path) -> String {
+    path.to_string_lossy().into_owned()
 }
 
 fn user() -> Option<String> {
@@ -99,14 +314,37 @@ fn is_root() -> bool {
     users::get_current_uid() == 0
 }
 
-fn time() -> time::OffsetDateTime {
-    time::OffsetDateTime::now_local().unwrap()
+fn time(env: &env::Env) -> time::OffsetDateTime {
+    time::OffsetDateTime::now_utc().to_offset(env.utc_offset())
 }
 
-fn power_info() -> power::PowerInfo {
-    power::PowerInfo::new()
+// the transient prompt, the continuation prompts and the right side prompt
+// don't show the repository, so there's no need to wait for it (unless it's
+// going into the title)
+fn needs_vcs(opts: &args::CommandLineOptions) -> bool {
+    if shows_everything(opts) {
+        return true;
+    }
+    let primary =
+        opts.kind == args::Kind::Main && opts.side != args::Side::Right;
+    primary && (!opts.transient || opts.title.is_some())
 }
 
-fn vcs_info() -> Option<Box<dyn vcs::VcsInfo>> {
-    vcs::detect()
+// the battery is only drawn on the right side of the main prompt
+fn needs_power(opts: &args::CommandLineOptions) -> bool {
+    if shows_everything(opts) {
+        return true;
+    }
+    opts.kind == args::Kind::Main
+        && !opts.transient
+        && opts.side != args::Side::Left
+}
+
+fn shows_everything(opts: &args::CommandLineOptions) -> bool {
+    matches!(opts.command, args::Command::Explain)
+        || opts.format == args::OutputFormat::Json
+}
+
+fn power_info() -> power::PowerInfo {
+    power::PowerInfo::new()
 }
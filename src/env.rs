@@ -0,0 +1,68 @@
+// the environment (and working directory) that a prompt is being drawn
+// for. this is normally just the process's own, but the daemon draws
+// prompts for other processes, and it can't just adopt theirs: changing the
+// process environment while other threads are running isn't sound.
+#[derive(Debug, Clone)]
+pub struct Env {
+    vars: std::collections::HashMap<std::ffi::OsString, std::ffi::OsString>,
+    cwd: Option<std::path::PathBuf>,
+    utc_offset: time::UtcOffset,
+}
+
+impl Env {
+    // this needs to happen before any other threads are started, since
+    // determining the local time offset is unsound in a multithreaded
+    // process (and so fails)
+    pub fn current() -> Env {
+        Env::new(
+            std::env::vars_os(),
+            std::env::current_dir().ok(),
+            local_offset(),
+        )
+    }
+
+    pub fn new<I>(
+        vars: I,
+        cwd: Option<std::path::PathBuf>,
+        utc_offset: time::UtcOffset,
+    ) -> Env
+    where
+        I: IntoIterator<Item = (std::ffi::OsString, std::ffi::OsString)>,
+    {
+        Env {
+            vars: vars.into_iter().collect(),
+            cwd,
+            utc_offset,
+        }
+    }
+
+    // like std::env::var, None if it isn't set or isn't valid unicode
+    pub fn var(&self, key: &str) -> Option<&str> {
+        self.var_os(key).and_then(|val| val.to_str())
+    }
+
+    pub fn var_os(&self, key: &str) -> Option<&std::ffi::OsStr> {
+        self.vars
+            .get(std::ffi::OsStr::new(key))
+            .map(|val| val.as_os_str())
+    }
+
+    pub fn cwd(&self) -> Option<&std::path::Path> {
+        self.cwd.as_deref()
+    }
+
+    pub fn utc_offset(&self) -> time::UtcOffset {
+        self.utc_offset
+    }
+}
+
+impl Default for Env {
+    fn default() -> Env {
+        Env::new([], None, time::UtcOffset::UTC)
+    }
+}
+
+// see Env::current
+pub fn local_offset() -> time::UtcOffset {
+    time::UtcOffset::current_local_offset().unwrap_or(time::UtcOffset::UTC)
+}
@@ -0,0 +1,280 @@
+use crate::colors;
+
+// which characters the prompt is drawn with. nerd fonts patch the
+// powerline symbols and a set of icons into the private use area.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GlyphSet {
+    Ascii,
+    NerdFont,
+}
+
+// every default glyph here takes up a single column (nerd fonts' "mono"
+// variants are built to make sure of that). ones from FANCY_PROMPT_GLYPHS
+// can be wider, so widths are always measured with width().
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glyphs {
+    pub path_open: String,
+    pub path_close: String,
+    pub separator: String,
+    pub battery_open: String,
+    pub battery_close: String,
+    pub time_open: String,
+    pub time_close: String,
+    pub border: String,
+    // drawn instead of the space at the start of the first line, and in
+    // front of the second line
+    pub corner_top: String,
+    pub corner_bottom: String,
+    // drawn in front of the vcs status
+    pub branch: String,
+    // drawn in front of a path which isn't writable
+    pub lock: String,
+    // drawn in front of the battery meter, from empty to full
+    pub battery_levels: &'static [&'static str],
+    errors: Vec<String>,
+}
+
+// the widest that a glyph from FANCY_PROMPT_GLYPHS can be
+const MAX_WIDTH: usize = 2;
+
+// the glyphs which can be overridden in FANCY_PROMPT_GLYPHS
+const ASCII_GLYPHS: [(&str, &str); 12] = [
+    ("path_open", "("),
+    ("path_close", ")"),
+    ("separator", "|"),
+    ("battery_open", "{"),
+    ("battery_close", "}"),
+    ("time_open", "["),
+    ("time_close", "]"),
+    ("border", "-"),
+    ("corner_top", ""),
+    ("corner_bottom", ""),
+    ("branch", ""),
+    ("lock", ""),
+];
+
+const NERD_FONT_GLYPHS: [(&str, &str); 12] = [
+    ("path_open", "\u{e0b6}"),
+    ("path_close", "\u{e0b4}"),
+    ("separator", "\u{e0b1}"),
+    ("battery_open", "\u{e0b6}"),
+    ("battery_close", "\u{e0b4}"),
+    ("time_open", "\u{e0b6}"),
+    ("time_close", "\u{e0b4}"),
+    ("border", "\u{2500}"),
+    ("corner_top", ""),
+    ("corner_bottom", ""),
+    ("branch", "\u{e0a0}"),
+    ("lock", "\u{e0a2}"),
+];
+
+const NERD_FONT_BATTERY_LEVELS: [&str; 5] =
+    ["\u{f244}", "\u{f243}", "\u{f242}", "\u{f241}", "\u{f240}"];
+
+impl GlyphSet {
+    pub fn from_str(set: &str) -> Option<Self> {
+        match set {
+            "ascii" => Some(GlyphSet::Ascii),
+            "nerd-font" => Some(GlyphSet::NerdFont),
+            _ => None,
+        }
+    }
+}
+
+impl Glyphs {
+    pub fn new(set: GlyphSet, env: &crate::env::Env) -> Self {
+        let (defaults, battery_levels): (_, &[&str]) = match set {
+            GlyphSet::Ascii => (ASCII_GLYPHS, &[]),
+            GlyphSet::NerdFont => {
+                (NERD_FONT_GLYPHS, &NERD_FONT_BATTERY_LEVELS)
+            }
+        };
+        let mut glyphs = Glyphs {
+            path_open: String::new(),
+            path_close: String::new(),
+            separator: String::new(),
+            battery_open: String::new(),
+            battery_close: String::new(),
+            time_open: String::new(),
+            time_close: String::new(),
+            border: String::new(),
+            corner_top: String::new(),
+            corner_bottom: String::new(),
+            branch: String::new(),
+            lock: String::new(),
+            battery_levels,
+            errors: vec![],
+        };
+        for (name, glyph) in defaults {
+            glyphs.set(name, glyph).unwrap();
+        }
+        if let Some(val) = env.var("FANCY_PROMPT_GLYPHS") {
+            glyphs.add_glyphs(val);
+        }
+        glyphs
+    }
+
+    // "border=─,corner_top=┌,corner_bottom=└". like FANCY_PROMPT_COLORS,
+    // bad entries are skipped (and reported), and "\," and "\=" are a
+    // literal "," and "=".
+    fn add_glyphs(&mut self, val: &str) {
+        for item in colors::split_unescaped(val, ',') {
+            if item.is_empty() {
+                continue;
+            }
+            let res = colors::find_unescaped(item, '=')
+                .map(|idx| {
+                    (
+                        colors::unescape(&item[..idx]),
+                        colors::unescape(&item[idx + 1..]),
+                    )
+                })
+                .ok_or_else(|| format!("{}: not a key=value pair", item))
+                .and_then(|(name, glyph)| {
+                    self.set(&name, &glyph)
+                        .map_err(|e| format!("{}: {}", name, e))
+                });
+            if let Err(e) = res {
+                self.errors.push(e);
+            }
+        }
+    }
+
+    fn set(&mut self, name: &str, glyph: &str) -> Result<(), String> {
+        let field = match name {
+            "path_open" => &mut self.path_open,
+            "path_close" => &mut self.path_close,
+            "separator" => &mut self.separator,
+            "battery_open" => &mut self.battery_open,
+            "battery_close" => &mut self.battery_close,
+            "time_open" => &mut self.time_open,
+            "time_close" => &mut self.time_close,
+            "border" => &mut self.border,
+            "corner_top" => &mut self.corner_top,
+            "corner_bottom" => &mut self.corner_bottom,
+            "branch" => &mut self.branch,
+            "lock" => &mut self.lock,
+            _ => return Err(String::from("unknown glyph")),
+        };
+        // the border is repeated to fill the line, so it has to be exactly
+        // one column wide
+        if name == "border" && width(glyph) != 1 {
+            return Err(String::from("must be a single column wide"));
+        }
+        if glyph.chars().any(char::is_control) {
+            return Err(String::from("contains control characters"));
+        }
+        // combining characters and the like would attach to whatever is
+        // drawn before them
+        if !glyph.is_empty() && width(glyph) == 0 {
+            return Err(String::from("has no width"));
+        }
+        // the rest of the line has to fit around them, so a long one would
+        // leave no room for the path
+        if width(glyph) > MAX_WIDTH {
+            return Err(format!(
+                "must be at most {} columns wide",
+                MAX_WIDTH
+            ));
+        }
+        *field = String::from(glyph);
+        Ok(())
+    }
+
+    // the FANCY_PROMPT_GLYPHS entries which were skipped, and why
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    // everything wrong with FANCY_PROMPT_GLYPHS
+    pub fn check_config(env: &crate::env::Env) -> Vec<String> {
+        Self::new(GlyphSet::Ascii, env)
+            .errors
+            .iter()
+            .map(|error| format!("FANCY_PROMPT_GLYPHS: {}", error))
+            .collect()
+    }
+
+    // the icon for a battery which is this full, if there are any
+    pub fn battery_level(&self, usage: f64) -> &'static str {
+        if self.battery_levels.is_empty() {
+            return "";
+        }
+        let max = self.battery_levels.len() - 1;
+        let level = (usage.clamp(0.0, 1.0) * max as f64).round() as usize;
+        self.battery_levels[level]
+    }
+
+    // every battery icon is the same width, so that the meter is too
+    pub fn battery_level_width(&self) -> usize {
+        width(self.battery_level(0.0))
+    }
+}
+
+// the number of columns that the text takes up in the terminal (wide
+// characters take two)
+pub fn width(text: &str) -> usize {
+    unicode_width::UnicodeWidthStr::width(text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_add_glyphs() {
+        let mut glyphs =
+            Glyphs::new(GlyphSet::Ascii, &crate::env::Env::default());
+        glyphs.errors.clear();
+        glyphs.add_glyphs(
+            "border=\u{2500},corner_top=\u{250c},,corner_bottom=\u{2514},\
+             border=--,bracket=<,time_open,lock=\u{301},border=\u{4e00},\
+             path_open=<<<,path_close=\u{4e00}\u{4e00},time_close=>>,\
+             separator=\\,,path_open=\\=,battery_open=\\",
+        );
+        assert_eq!(glyphs.border, "\u{2500}");
+        assert_eq!(glyphs.corner_top, "\u{250c}");
+        assert_eq!(glyphs.corner_bottom, "\u{2514}");
+        assert_eq!(glyphs.time_open, "[");
+        assert_eq!(glyphs.time_close, ">>");
+        assert_eq!(glyphs.separator, ",");
+        assert_eq!(glyphs.path_open, "=");
+        assert_eq!(glyphs.battery_open, "\\");
+        assert_eq!(
+            glyphs.errors(),
+            [
+                "border: must be a single column wide",
+                "bracket: unknown glyph",
+                "time_open: not a key=value pair",
+                "lock: has no width",
+                "border: must be a single column wide",
+                "path_open: must be at most 2 columns wide",
+                "path_close: must be at most 2 columns wide",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_width() {
+        assert_eq!(width("(~/src)"), 7);
+        assert_eq!(width("\u{2500}"), 1);
+        assert_eq!(width("\u{4e00}"), 2);
+        assert_eq!(width("e\u{301}"), 1);
+        assert_eq!(width(""), 0);
+    }
+
+    #[test]
+    fn test_battery_level() {
+        let ascii = Glyphs::new(GlyphSet::Ascii, &crate::env::Env::default());
+        assert_eq!(ascii.battery_level(0.5), "");
+        assert_eq!(ascii.battery_level_width(), 0);
+
+        let nerd_font =
+            Glyphs::new(GlyphSet::NerdFont, &crate::env::Env::default());
+        assert_eq!(nerd_font.battery_level(0.0), "\u{f244}");
+        assert_eq!(nerd_font.battery_level(0.5), "\u{f242}");
+        assert_eq!(nerd_font.battery_level(0.9), "\u{f240}");
+        assert_eq!(nerd_font.battery_level(1.5), "\u{f240}");
+        assert_eq!(nerd_font.battery_level_width(), 1);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::args;
+
+pub fn script(opts: &args::InitOptions) -> String {
+    let flags = if opts.semantic_marks {
+        " --semantic-marks"
+    } else {
+        ""
+    };
+    match opts.shell {
+        args::InitShell::Bash => bash(opts, flags),
+        args::InitShell::Zsh => zsh(opts, flags),
+        args::InitShell::Fish => fish(opts, flags),
+    }
+}
+
+// bash has no way to redraw the prompt of an accepted line, so transient
+// prompts aren't supported there. the hook goes first so that it sees the
+// exit status of the command, and is only added once so that sourcing this
+// again (from a re-read .bashrc, say) doesn't run it twice.
+fn bash(opts: &args::InitOptions, flags: &str) -> String {
+    let mut script = format!(
+        "__fancy_prompt () {{
+    local __err=$?
+    PS1=\"$(fancy-prompt --prompt-escape bash{} \"$__err\")\"
+}}
+case \";$PROMPT_COMMAND;\" in
+    *\";__fancy_prompt;\"*) ;;
+    *) PROMPT_COMMAND=\"__fancy_prompt${{PROMPT_COMMAND:+;$PROMPT_COMMAND}}\" ;;
+esac
+",
+        flags
+    );
+    if opts.semantic_marks {
+        // PS0 is expanded after a command is read, just before it runs
+        script.push_str("PS0='\\e]133;C\\a'\n");
+    }
+    script
+}
+
+fn zsh(opts: &args::InitOptions, flags: &str) -> String {
+    let mut script = format!(
+        "function _fancy_prompt_precmd () {{
+    _fancy_prompt_error_code=$?
+    PROMPT=`fancy-prompt --prompt-escape zsh{} $_fancy_prompt_error_code`
+}}
+precmd_functions+=(_fancy_prompt_precmd)
+",
+        flags
+    );
+    // the full prompt is redrawn in its minimal form once the line is
+    // accepted, so only the current prompt takes up two lines
+    if opts.transient {
+        script.push_str(&format!(
+            "function _fancy_prompt_line_finish () {{
+    PROMPT=`fancy-prompt --prompt-escape zsh --transient{} \
+$_fancy_prompt_error_code`
+    zle reset-prompt
+}}
+autoload -Uz add-zle-hook-widget
+add-zle-hook-widget line-finish _fancy_prompt_line_finish
+",
+            flags
+        ));
+    }
+    if opts.semantic_marks {
+        script.push_str(
+            "function _fancy_prompt_preexec () {
+    print -n '\\e]133;C\\a'
+}
+preexec_functions+=(_fancy_prompt_preexec)
+",
+        );
+    }
+    script
+}
+
+fn fish(opts: &args::InitOptions, flags: &str) -> String {
+    // fish (4.1 and later) redraws the prompt with --final-rendering once
+    // the line is accepted
+    let mut script = if opts.transient {
+        format!(
+            "function fish_prompt
+    if contains -- --final-rendering $argv
+        fancy-prompt --transient{} $status
+    else
+        fancy-prompt{} $status
+    end
+end
+set -g fish_transient_prompt 1
+",
+            flags, flags
+        )
+    } else {
+        format!(
+            "function fish_prompt
+    fancy-prompt{} $status
+end
+",
+            flags
+        )
+    };
+    if opts.semantic_marks {
+        script.push_str(
+            "function _fancy_prompt_preexec --on-event fish_preexec
+    printf '\\e]133;C\\a'
+end
+",
+        );
+    }
+    script
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bash_script(semantic_marks: bool) -> String {
+        script(&args::InitOptions {
+            shell: args::InitShell::Bash,
+            transient: false,
+            semantic_marks,
+        })
+    }
+
+    fn run_bash(script: &str, args: &[&str]) -> std::process::Output {
+        std::process::Command::new("bash")
+            .args(["--norc", "--noprofile"])
+            .args(args)
+            .env("FANCY_PROMPT_INIT", script)
+            .output()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bash_syntax() {
+        for semantic_marks in [false, true] {
+            let script = bash_script(semantic_marks);
+            let output = run_bash(&script, &["-n", "-c", &script]);
+            assert!(output.status.success(), "{}", script);
+        }
+    }
+
+    #[test]
+    fn test_bash_prompt_command() {
+        let script = bash_script(false);
+        let print = r#"eval "$FANCY_PROMPT_INIT"; \
+            eval "$FANCY_PROMPT_INIT"; \
+            printf %s "$PROMPT_COMMAND""#;
+
+        let output = run_bash(
+            &script,
+            &["-c", &format!("unset PROMPT_COMMAND; {}", print)],
+        );
+        assert_eq!(output.stdout, b"__fancy_prompt");
+
+        let output = run_bash(
+            &script,
+            &["-c", &format!("PROMPT_COMMAND=history\\ -a; {}", print)],
+        );
+        assert_eq!(output.stdout, b"__fancy_prompt;history -a");
+    }
+}
@@ -3,35 +3,134 @@ mod verbose;
 
 mod args;
 mod colors;
+#[cfg(target_os = "linux")]
+mod daemon;
 mod data;
+mod dirs;
+mod env;
+mod glyphs;
+mod init;
 mod power;
 mod prompt;
 mod sys;
+mod terminal;
 mod vcs;
 
 fn main() {
     start_talking_about_time!("main");
     let opts = args::parse();
+    verbose::enable(opts.profile);
     talk_about_time!("parsing args");
+    run(opts);
+    stop_talking_about_time!();
+    verbose::finish();
+}
+
+fn run(opts: args::CommandLineOptions) {
+    match opts.command {
+        args::Command::Prompt => {}
+        args::Command::InvalidateCache => {
+            if let Err(e) = vcs::invalidate_cache(&opts.env) {
+                eprintln!("couldn't invalidate cache: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        args::Command::Daemon => {
+            run_daemon();
+            return;
+        }
+        args::Command::Init(init_opts) => {
+            print!("{}", init::script(&init_opts));
+            return;
+        }
+        args::Command::CheckConfig => {
+            let mut errors = colors::Colors::check_config(&opts.env);
+            errors.extend(glyphs::Glyphs::check_config(&opts.env));
+            if errors.is_empty() {
+                println!("no problems found");
+                return;
+            }
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            std::process::exit(1);
+        }
+        args::Command::Explain => {
+            let data = data::collect(opts);
+            prompt::Prompt::new(data)
+                .explain(std::io::stdout())
+                .unwrap();
+            return;
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if opts.use_daemon && opts.theme != colors::Theme::Auto {
+        if let Some(prompt) = daemon::request() {
+            talk_about_time!("rendering in daemon");
+            std::io::Write::write_all(&mut std::io::stdout(), &prompt)
+                .unwrap();
+            return;
+        }
+        talk_about_time!("looking for daemon");
+    }
+    let format = opts.format;
     let data = data::collect(opts);
     talk_about_time!("collecting data");
     let w = std::io::stdout();
-    prompt::Prompt::new(data).display(w);
+    let result = prompt::render(format, data, w);
     talk_about_time!("displaying data");
-    stop_talking_about_time!();
+    match result {
+        Ok(()) => {}
+        // the reader (jq, head, ...) went away, which isn't our problem
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+        Err(e) => {
+            eprintln!("couldn't write prompt: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn run_daemon() {
+    if let Err(e) = daemon::run() {
+        eprintln!("couldn't start daemon: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run_daemon() {
+    eprintln!("daemon mode is only supported on linux");
+    std::process::exit(1);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::TimeZone;
 
-    #[test]
-    fn test_render() {
-        let data = data::PromptData {
+    pub(crate) fn test_data() -> data::PromptData {
+        data::PromptData {
             shell: colors::ShellType::Unknown,
+            side: args::Side::Full,
+            transient: false,
+            kind: args::Kind::Main,
+            semantic_marks: false,
+            report_cwd: false,
+            title: None,
+            hyperlinks: false,
+            branch_url: None,
+            bright_bold: true,
+            theme: colors::Theme::Dark,
+            auto_colors: false,
+            color: terminal::ColorMode::Always,
+            ansi: false,
+            glyphs: glyphs::GlyphSet::Ascii,
             error_code: 0,
+            vcs_age: false,
+            vcs_stale_after: std::time::Duration::from_secs(7 * 24 * 60 * 60),
             hostname: Some(String::from("hush")),
+            full_hostname: Some(String::from("hush.tozt.net")),
             terminal_cols: Some(80),
             pwd: Some(std::path::PathBuf::from(
                 "/home/doy/coding/fancy-prompt",
@@ -39,13 +138,219 @@ mod tests {
             home: Some(std::path::PathBuf::from("/home/doy")),
             user: Some(String::from("doy")),
             is_root: false,
-            time: chrono::Local
-                .with_ymd_and_hms(2018, 5, 14, 17, 35, 45)
-                .unwrap(),
-            power_info: power::PowerInfo::new(),
-            vcs_info: None,
-        };
+            time: time::Date::from_calendar_date(2018, time::Month::May, 14)
+                .unwrap()
+                .with_hms(17, 35, 45)
+                .unwrap()
+                .assume_utc(),
+            power_info: data::Collected::Ready(power::PowerInfo::new()),
+            vcs_info: data::Collected::Ready(None),
+            env: env::Env::default(),
+        }
+    }
+
+    #[test]
+    fn test_render() {
         let w = vec![];
-        prompt::Prompt::new(data).display(w);
+        prompt::Prompt::new(test_data()).display(w);
+    }
+
+    #[test]
+    fn test_render_sides() {
+        let render = |side| {
+            let mut w = vec![];
+            let data = data::PromptData {
+                side,
+                ..test_data()
+            };
+            prompt::Prompt::new(data).display(&mut w);
+            String::from_utf8(w).unwrap()
+        };
+        assert!(render(args::Side::Full).contains("17:35:45"));
+        assert!(!render(args::Side::Left).contains("17:35:45"));
+        assert!(render(args::Side::Left).contains("doy"));
+        assert!(render(args::Side::Right).contains("17:35:45"));
+        assert!(!render(args::Side::Right).contains("doy"));
+        assert!(!render(args::Side::Right).contains('\n'));
+    }
+
+    #[test]
+    fn test_render_transient() {
+        let mut w = vec![];
+        let data = data::PromptData {
+            transient: true,
+            ..test_data()
+        };
+        prompt::Prompt::new(data).display(&mut w);
+        let prompt = String::from_utf8(w).unwrap();
+        assert!(prompt.contains("17:35:45"));
+        assert!(prompt.contains('$'));
+        assert!(!prompt.contains("doy"));
+        assert!(!prompt.contains('\n'));
+    }
+
+    #[test]
+    fn test_render_continuation() {
+        let render = |kind| {
+            let mut w = vec![];
+            let data = data::PromptData {
+                kind,
+                ..test_data()
+            };
+            prompt::Prompt::new(data).display(&mut w);
+            String::from_utf8(w).unwrap()
+        };
+        let main = render(args::Kind::Main);
+        let continuation = render(args::Kind::Continuation);
+        let select = render(args::Kind::Select);
+        assert!(continuation.contains("..."));
+        assert!(continuation.contains('>'));
+        assert!(select.contains('?'));
+        assert!(!continuation.contains('\n'));
+
+        // the second line of the main prompt and the continuation prompt
+        // should take up the same number of columns
+        let width = |s: &str| {
+            let s = regex::Regex::new("\x1b(\\[[0-9;]*m|\\(B)")
+                .unwrap()
+                .replace_all(s, "")
+                .into_owned();
+            s.chars().count()
+        };
+        assert_eq!(
+            width(main.rsplit('\n').next().unwrap()),
+            width(&continuation)
+        );
+        assert_eq!(width(&continuation), width(&select));
+    }
+
+    #[test]
+    fn test_render_semantic_marks() {
+        let mut w = vec![];
+        let data = data::PromptData {
+            error_code: 3,
+            semantic_marks: true,
+            ..test_data()
+        };
+        prompt::Prompt::new(data).display(&mut w);
+        let prompt = String::from_utf8(w).unwrap();
+        assert!(prompt.starts_with("\x1b]133;D;3\x07\x1b]133;A\x07"));
+        assert!(prompt.ends_with("\x1b]133;B\x07"));
+
+        let mut w = vec![];
+        prompt::Prompt::new(test_data()).display(&mut w);
+        assert!(!String::from_utf8(w).unwrap().contains("133;"));
+    }
+
+    #[test]
+    fn test_render_report_cwd() {
+        let mut w = vec![];
+        let data = data::PromptData {
+            report_cwd: true,
+            ..test_data()
+        };
+        prompt::Prompt::new(data).display(&mut w);
+        let prompt = String::from_utf8(w).unwrap();
+        // the url needs the full hostname, even though only the first
+        // part of it is displayed
+        assert!(prompt.starts_with("\x1b]7;file://hush.tozt.net/"));
+        assert_eq!(prompt.matches("hush.tozt.net").count(), 1);
+        assert_eq!(prompt.matches("hush").count(), 2);
+    }
+
+    #[test]
+    fn test_render_no_color() {
+        let render = |color| {
+            let mut w = vec![];
+            let data = data::PromptData {
+                color,
+                ..test_data()
+            };
+            prompt::Prompt::new(data).display(&mut w);
+            String::from_utf8(w).unwrap()
+        };
+        let plain = render(terminal::ColorMode::Never);
+        assert!(!plain.contains('\x1b'));
+        assert!(plain.contains("doy"));
+        assert!(render(terminal::ColorMode::Always).contains('\x1b'));
+    }
+
+    #[test]
+    fn test_render_ansi() {
+        let mut w = vec![];
+        let data = data::PromptData {
+            ansi: true,
+            ..test_data()
+        };
+        prompt::Prompt::new(data).display(&mut w);
+        let prompt = String::from_utf8(w).unwrap();
+        assert!(prompt.contains("\x1b[33m"));
+        assert!(prompt.contains("\x1b[0m"));
+    }
+
+    #[test]
+    fn test_render_glyphs() {
+        let render = |glyphs, terminal_cols| {
+            let mut w = vec![];
+            let data = data::PromptData {
+                glyphs,
+                terminal_cols,
+                color: terminal::ColorMode::Never,
+                ..test_data()
+            };
+            prompt::Prompt::new(data).display(&mut w);
+            String::from_utf8(w).unwrap()
+        };
+        let first_line_width =
+            |prompt: &str| glyphs::width(prompt.lines().next().unwrap());
+        let ascii = render(glyphs::GlyphSet::Ascii, Some(100));
+        assert!(ascii.contains(" ("));
+        assert!(ascii.contains(") -"));
+        let nerd_font = render(glyphs::GlyphSet::NerdFont, Some(100));
+        assert!(nerd_font.contains(" \u{e0b6}"));
+        assert!(nerd_font.contains("\u{e0b4} \u{2500}"));
+        assert!(!nerd_font.contains(" ("));
+        assert_eq!(first_line_width(&ascii), 100);
+        assert_eq!(first_line_width(&nerd_font), 100);
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut w = vec![];
+        prompt::Prompt::new(test_data()).explain(&mut w).unwrap();
+        let explanation = String::from_utf8(w).unwrap();
+        assert!(explanation.contains("user: doy\n"));
+        assert!(explanation.contains("path: /home/doy/coding/fancy-prompt\n"));
+        assert!(explanation.contains("  does not exist (anymore)\n"));
+        assert!(
+            explanation.contains("  color: path_not_exist = red (default)\n")
+        );
+        assert!(explanation.contains("vcs: not in a repository\n"));
+    }
+
+    #[test]
+    fn test_json() {
+        let mut w = vec![];
+        prompt::render(args::OutputFormat::Json, test_data(), &mut w)
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&w).unwrap();
+        assert_eq!(json["user"], "doy");
+        assert_eq!(json["hostname"], "hush");
+        assert_eq!(json["pwd"], "/home/doy/coding/fancy-prompt");
+        assert_eq!(json["error_code"], 0);
+        assert_eq!(json["time"], "2018-05-14T17:35:45Z");
+        assert!(json["vcs"].is_null());
+        assert_eq!(json["timed_out"], serde_json::json!([]));
+
+        let mut data = test_data();
+        data.pwd = Some(std::path::PathBuf::from(
+            <std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(
+                b"/tmp/\xff",
+            ),
+        ));
+        let mut w = vec![];
+        prompt::render(args::OutputFormat::Json, data, &mut w).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&w).unwrap();
+        assert_eq!(json["pwd"], "/tmp/\u{fffd}");
     }
 }
@@ -4,11 +4,19 @@ mod sys;
 
 // XXX maybe extract this out into a separate crate?
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct PowerInfo {
     power_supplies: Vec<sys::PowerSupplyInfo>,
 }
 
+// a plain copy of the battery state, for --format json
+#[derive(Debug, serde::Serialize)]
+pub struct Snapshot {
+    pub has_batteries: bool,
+    pub battery_usage: Option<f64>,
+    pub charging: bool,
+}
+
 impl PowerInfo {
     pub fn new() -> PowerInfo {
         let power_supplies = sys::power_supplies();
@@ -48,6 +56,18 @@ impl PowerInfo {
         false
     }
 
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            has_batteries: self.has_batteries(),
+            battery_usage: self.battery_usage(),
+            charging: self.charging(),
+        }
+    }
+
+    pub fn power_supplies(&self) -> &[sys::PowerSupplyInfo] {
+        &self.power_supplies
+    }
+
     pub fn has_batteries(&self) -> bool {
         self.batteries().count() > 0
     }
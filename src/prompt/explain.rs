@@ -0,0 +1,326 @@
+use crate::sys;
+use crate::vcs;
+
+impl super::Prompt {
+    // prints everything that went into deciding what the prompt looks like,
+    // for figuring out why it looks the way it does
+    pub fn explain<W: std::io::Write>(
+        &self,
+        mut w: W,
+    ) -> std::io::Result<()> {
+        let data = &self.data;
+        let user = data.user.as_deref().unwrap_or("???");
+        let host = data.hostname.as_deref().unwrap_or("???");
+
+        writeln!(w, "user: {}", user)?;
+        writeln!(w, "  root: {}", yes_no(data.is_root))?;
+        writeln!(
+            w,
+            "  color: {}",
+            self.colors.describe(&format!("user_{}", user))
+        )?;
+
+        writeln!(w, "host: {}", host)?;
+        writeln!(
+            w,
+            "  color: {}",
+            self.colors.describe(&format!("host_{}", host))
+        )?;
+
+        writeln!(w, "shell: {:?}", data.shell)?;
+        writeln!(w, "theme: {:?}", data.theme)?;
+        for error in self.colors.errors() {
+            writeln!(w, "  ignoring FANCY_PROMPT_COLORS entry {}", error)?;
+        }
+        writeln!(w, "glyphs: {:?}", data.glyphs)?;
+        for error in self.glyphs.errors() {
+            writeln!(w, "  ignoring FANCY_PROMPT_GLYPHS entry {}", error)?;
+        }
+        writeln!(
+            w,
+            "terminal width: {}",
+            data.terminal_cols
+                .map(|cols| format!("{} columns", cols))
+                .unwrap_or_else(|| String::from("unknown, assuming 80"))
+        )?;
+        writeln!(w, "time: {}", super::format_time(data.time))?;
+        writeln!(w, "  color: {}", self.colors.describe("default"))?;
+
+        self.explain_path(&mut w)?;
+        self.explain_vcs(&mut w)?;
+        self.explain_battery(&mut w)?;
+
+        writeln!(w, "error code: {}", data.error_code)?;
+        writeln!(
+            w,
+            "  color: {}",
+            self.colors
+                .describe(super::error_code_color(data.error_code))
+        )?;
+
+        writeln!(w, "prompt: {}", if data.is_root { "#" } else { "$" })?;
+        writeln!(
+            w,
+            "  color: {}",
+            self.colors.describe(&format!("user_{}", user))
+        )?;
+
+        Ok(())
+    }
+
+    fn explain_path<W: std::io::Write>(
+        &self,
+        w: &mut W,
+    ) -> std::io::Result<()> {
+        let pwd = self.data.pwd.as_deref();
+        writeln!(
+            w,
+            "path: {}",
+            pwd.map(|pwd| pwd.to_string_lossy().into_owned())
+                .unwrap_or_else(|| String::from("unknown ($PWD is not set)"))
+        )?;
+        writeln!(
+            w,
+            "  home: {}",
+            self.data
+                .home
+                .as_deref()
+                .map(|home| home.to_string_lossy().into_owned())
+                .unwrap_or_else(|| String::from(
+                    "unknown ($HOME is not set)"
+                ))
+        )?;
+
+        let color = super::path_color(pwd);
+        let reason = pwd.map(|pwd| match sys::path_writable(pwd) {
+            sys::PathWritability::Writable => String::from("writable"),
+            sys::PathWritability::NotWritable => format!(
+                "not writable: {}",
+                sys::describe_path_permissions(pwd).unwrap_or_default()
+            ),
+            sys::PathWritability::NotExist => {
+                String::from("does not exist (anymore)")
+            }
+        });
+        if let Some(reason) = reason {
+            writeln!(w, "  {}", reason)?;
+        }
+        writeln!(w, "  color: {}", self.colors.describe(&color))?;
+
+        Ok(())
+    }
+
+    fn explain_vcs<W: std::io::Write>(
+        &self,
+        w: &mut W,
+    ) -> std::io::Result<()> {
+        if self.data.vcs_info.is_timed_out() {
+            writeln!(w, "vcs: timed out")?;
+            writeln!(w, "  color: {}", self.colors.describe("default"))?;
+            return Ok(());
+        }
+        let vcs_info = if let Some(vcs_info) = self.vcs_info() {
+            vcs_info
+        } else {
+            writeln!(w, "vcs: not in a repository")?;
+            return Ok(());
+        };
+
+        let vcs = self.format_vcs().map(|vcs| vcs.to_string());
+        writeln!(w, "vcs: {}", vcs.unwrap_or_default())?;
+        writeln!(w, "  type: {:?}", vcs_info.vcs())?;
+        writeln!(
+            w,
+            "  modified files: {}",
+            yes_no(vcs_info.has_modified_files())
+        )?;
+        writeln!(
+            w,
+            "  staged files: {}",
+            yes_no(vcs_info.has_staged_files())
+        )?;
+        writeln!(w, "  new files: {}", yes_no(vcs_info.has_new_files()))?;
+        writeln!(w, "  commits: {}", yes_no(vcs_info.has_commits()))?;
+        writeln!(
+            w,
+            "  branch: {}",
+            vcs_info.branch().unwrap_or_else(|| String::from("unknown"))
+        )?;
+        writeln!(
+            w,
+            "  default branch: {}",
+            vcs_info
+                .default_branch()
+                .unwrap_or_else(|| String::from("unknown"))
+        )?;
+        writeln!(w, "  upstream: {}", upstream_status(vcs_info))?;
+        writeln!(
+            w,
+            "  remote url: {}",
+            vcs_info
+                .remote_url()
+                .unwrap_or_else(|| String::from("none"))
+        )?;
+        if self.data.hyperlinks {
+            writeln!(
+                w,
+                "  links to: {}",
+                self.branch_url().unwrap_or_else(|| String::from(
+                    "nothing (unknown forge)"
+                ))
+            )?;
+        }
+        writeln!(w, "  active operation: {:?}", vcs_info.active_operation())?;
+        if let Some(age) =
+            super::format_vcs_age(Some(vcs_info), self.data.time)
+        {
+            writeln!(w, "  last commit: {} ago", age)?;
+        }
+        writeln!(
+            w,
+            "  last fetch: {}",
+            vcs_info
+                .last_fetch_time()
+                .map(|fetch_time| format!(
+                    "{} ago",
+                    super::format_age(self.data.time - fetch_time)
+                ))
+                .unwrap_or_else(|| String::from("never"))
+        )?;
+
+        let color = super::vcs_color(Some(vcs_info));
+        writeln!(w, "  color: {}", self.colors.describe(&color))?;
+        if vcs_info.is_error() {
+            writeln!(w, "    because: there are no commits yet")?;
+        } else if vcs_info.is_dirty() {
+            writeln!(
+                w,
+                "    because: {}",
+                dirty_reasons(vcs_info).join(", ")
+            )?;
+        }
+        if self.data.vcs_age {
+            let color = super::vcs_fetch_age_color(
+                Some(vcs_info),
+                self.data.time,
+                self.data.vcs_stale_after,
+            );
+            writeln!(
+                w,
+                "  fetch age color: {}",
+                self.colors.describe(&color)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn explain_battery<W: std::io::Write>(
+        &self,
+        w: &mut W,
+    ) -> std::io::Result<()> {
+        let power_info =
+            if let Some(power_info) = self.data.power_info.ready() {
+                power_info
+            } else {
+                writeln!(w, "battery: timed out")?;
+                return Ok(());
+            };
+
+        if !power_info.has_batteries() {
+            writeln!(w, "battery: none found")?;
+        }
+        for supply in power_info.power_supplies() {
+            writeln!(
+                w,
+                "power supply {}: {:?}, energy {}/{}, online {}",
+                supply.name,
+                supply.ty,
+                supply
+                    .energy_now
+                    .map(|n| n.to_string())
+                    .unwrap_or_else(|| String::from("?")),
+                supply
+                    .energy_full
+                    .map(|n| n.to_string())
+                    .unwrap_or_else(|| String::from("?")),
+                supply.online.map(yes_no).unwrap_or("?"),
+            )?;
+        }
+        if !power_info.has_batteries() {
+            return Ok(());
+        }
+
+        let charging = power_info.charging();
+        if let Some(usage) = power_info.battery_usage() {
+            writeln!(
+                w,
+                "battery: {:.0}%, {}",
+                usage * 100.0,
+                if charging { "charging" } else { "discharging" }
+            )?;
+            writeln!(
+                w,
+                "  color: {}",
+                self.colors.describe(super::battery_discharge_color(
+                    usage, charging
+                ))
+            )?;
+        } else {
+            writeln!(w, "battery: couldn't determine charge")?;
+            writeln!(w, "  color: {}", self.colors.describe("error"))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn upstream_status(vcs_info: &dyn vcs::VcsInfo) -> String {
+    match (vcs_info.upstream(), vcs_info.remote_branch_diff()) {
+        (Some(upstream), Some((local, remote))) => {
+            format!("{} ({} ahead, {} behind)", upstream, local, remote)
+        }
+        (Some(upstream), None) => format!("{} (missing)", upstream),
+        (None, _) => String::from("none (not on a branch)"),
+    }
+}
+
+fn dirty_reasons(vcs_info: &dyn vcs::VcsInfo) -> Vec<String> {
+    let mut reasons = vec![];
+    if vcs_info.has_modified_files() {
+        reasons.push(String::from("modified files"));
+    }
+    if vcs_info.has_staged_files() {
+        reasons.push(String::from("staged files"));
+    }
+    if vcs_info.has_new_files() {
+        reasons.push(String::from("new files"));
+    }
+    match vcs_info.remote_branch_diff() {
+        Some((local, remote)) => {
+            if local > 0 {
+                reasons.push(format!("{} commits ahead of upstream", local));
+            }
+            if remote > 0 {
+                reasons.push(format!("{} commits behind upstream", remote));
+            }
+        }
+        None => reasons.push(
+            vcs_info
+                .upstream()
+                .map(|upstream| format!("no upstream: {} missing", upstream))
+                .unwrap_or_else(|| {
+                    String::from("no upstream: not on a branch")
+                }),
+        ),
+    }
+    reasons
+}
+
+fn yes_no(b: bool) -> &'static str {
+    if b {
+        "yes"
+    } else {
+        "no"
+    }
+}
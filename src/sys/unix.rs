@@ -1,3 +1,5 @@
+use std::io::{Read as _, Write as _};
+use std::os::fd::AsRawFd as _;
 use std::os::unix::fs::MetadataExt;
 use std::os::unix::fs::PermissionsExt;
 
@@ -23,3 +25,197 @@ pub fn path_writable(path: &std::path::Path) -> super::PathWritability {
         })
         .unwrap_or(super::PathWritability::NotExist)
 }
+
+pub fn describe_path_permissions(path: &std::path::Path) -> Option<String> {
+    std::fs::metadata(path).ok().map(|stat| {
+        format!(
+            "owner uid {}, group gid {}, mode {:04o} (running as uid {}, \
+             gid {})",
+            stat.uid(),
+            stat.gid(),
+            stat.permissions().mode() & 0o7777,
+            users::get_effective_uid(),
+            users::get_effective_gid(),
+        )
+    })
+}
+
+// asks the terminal for its background color (OSC 11). this talks to the
+// terminal directly, since stdout is usually captured by the shell. the
+// background doesn't change for the life of a shell, so the answer is kept
+// (in the runtime dir) per terminal and session, and only asked for once.
+pub fn terminal_background(
+    timeout: std::time::Duration,
+) -> Option<(u8, u8, u8)> {
+    let tty = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .ok()?;
+
+    let cache = background_cache(&tty);
+    if let Some(cached) =
+        cache.as_ref().and_then(|path| std::fs::read(path).ok())
+    {
+        return parse_background(&cached);
+    }
+
+    // None means that we couldn't ask, rather than that there was no
+    // answer, so try again next time
+    let reply = ask_background(&tty, timeout)?;
+    if let Some(cache) = cache {
+        if let Some(parent) = cache.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let _ = std::fs::write(&cache, &reply);
+    }
+    parse_background(&reply)
+}
+
+// the session is the shell's, so a new shell on a reused pty (which may be a
+// different terminal entirely) asks again
+fn background_cache(tty: &std::fs::File) -> Option<std::path::PathBuf> {
+    let rdev = tty.metadata().ok()?.rdev();
+    let sid = unsafe { libc::getsid(0) };
+    if sid < 0 {
+        return None;
+    }
+    crate::dirs::runtime_dir()
+        .map(|dir| dir.join("background").join(format!("{}-{}", rdev, sid)))
+}
+
+// returns the OSC 11 reply (empty if the terminal doesn't support it)
+fn ask_background(
+    tty: &std::fs::File,
+    timeout: std::time::Duration,
+) -> Option<Vec<u8>> {
+    let fd = tty.as_raw_fd();
+
+    let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+    if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let orig = unsafe { termios.assume_init() };
+    // the reply shouldn't be echoed, and shouldn't wait for a newline
+    let mut raw = orig;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    raw.c_cc[libc::VMIN] = 0;
+    raw.c_cc[libc::VTIME] = 0;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+        return None;
+    }
+    // anything already waiting was typed ahead, and reading it along with
+    // the reply would throw it away
+    let reply = if poll_tty(tty, std::time::Duration::ZERO) {
+        None
+    } else {
+        query_background(tty, timeout)
+    };
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &orig) };
+
+    reply
+}
+
+fn poll_tty(tty: &std::fs::File, timeout: std::time::Duration) -> bool {
+    let mut pollfd = libc::pollfd {
+        fd: tty.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let ready = unsafe {
+        libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int)
+    };
+    ready > 0
+}
+
+// the query is followed by a primary device attributes request, which every
+// terminal answers (after answering the first query, if it understood it).
+// that way terminals without OSC 11 support don't cost the whole timeout,
+// and no reply is left to turn up in the line editor after we're done.
+fn query_background(
+    mut tty: &std::fs::File,
+    timeout: std::time::Duration,
+) -> Option<Vec<u8>> {
+    tty.write_all(b"\x1b]11;?\x07\x1b[c").ok()?;
+
+    let deadline = std::time::Instant::now() + timeout;
+    let mut reply = vec![];
+    loop {
+        if let Some(idx) = find_device_attributes(&reply) {
+            reply.truncate(idx);
+            return Some(reply);
+        }
+        let remaining =
+            deadline.saturating_duration_since(std::time::Instant::now());
+        if !poll_tty(tty, remaining) {
+            // a terminal this slow (or not a terminal at all) gets the
+            // default theme
+            return Some(vec![]);
+        }
+        let mut buf = [0; 64];
+        let len = tty.read(&mut buf).ok()?;
+        reply.extend_from_slice(&buf[..len]);
+    }
+}
+
+// "\e[?62;22c", returning where it starts
+fn find_device_attributes(reply: &[u8]) -> Option<usize> {
+    let start = reply.windows(3).position(|w| w == b"\x1b[?")?;
+    reply[start..].contains(&b'c').then_some(start)
+}
+
+// "\e]11;rgb:ffff/ffff/dddd\a", with 1 to 4 hex digits per component
+fn parse_background(reply: &[u8]) -> Option<(u8, u8, u8)> {
+    let reply = std::str::from_utf8(reply).ok()?;
+    let (_, rgb) = reply.split_once("rgb:")?;
+    let rgb = rgb.trim_end_matches(['\x07', '\x1b', '\\']);
+    let mut components = rgb.split('/').map(|component| {
+        if component.is_empty() || component.len() > 4 {
+            return None;
+        }
+        let max = 16u32.checked_pow(component.len() as u32)? - 1;
+        let value = u32::from_str_radix(component, 16).ok()?;
+        Some((value * 255 / max) as u8)
+    });
+    Some((
+        components.next()??,
+        components.next()??,
+        components.next()??,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_background() {
+        assert_eq!(
+            parse_background(b"\x1b]11;rgb:ffff/ffff/dddd\x07"),
+            Some((255, 255, 221))
+        );
+        assert_eq!(
+            parse_background(b"\x1b]11;rgb:00/2b/36\x1b\\"),
+            Some((0, 43, 54))
+        );
+        assert_eq!(
+            parse_background(b"\x1b]11;rgb:f/0/0\x07"),
+            Some((255, 0, 0))
+        );
+        assert_eq!(parse_background(b"\x1b]11;rgb:ff/ff\x07"), None);
+        assert_eq!(parse_background(b"\x1b]11;rgb://\x07"), None);
+        assert_eq!(parse_background(b"garbage"), None);
+        assert_eq!(parse_background(b""), None);
+    }
+
+    #[test]
+    fn test_find_device_attributes() {
+        assert_eq!(
+            find_device_attributes(b"\x1b]11;rgb:0/0/0\x07\x1b[?62;22c"),
+            Some(15)
+        );
+        assert_eq!(find_device_attributes(b"\x1b[?1;2c"), Some(0));
+        assert_eq!(find_device_attributes(b"\x1b]11;rgb:0/0/0\x07"), None);
+        assert_eq!(find_device_attributes(b"\x1b[?62;2"), None);
+    }
+}
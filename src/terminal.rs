@@ -0,0 +1,254 @@
+// whether to draw the prompt in color at all
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn from_str(mode: &str) -> Option<Self> {
+        match mode {
+            "auto" => Some(ColorMode::Auto),
+            "always" => Some(ColorMode::Always),
+            "never" => Some(ColorMode::Never),
+            _ => None,
+        }
+    }
+
+    // the prompt itself is always captured by the shell, so whether there
+    // is a terminal at all is decided by the caller (from stderr or stdin)
+    pub fn use_color(self, is_tty: bool, env: &crate::env::Env) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                let no_color =
+                    env.var_os("NO_COLOR").is_some_and(|val| !val.is_empty());
+                let dumb =
+                    env.var_os("TERM").is_some_and(|term| term == "dumb");
+                is_tty && !no_color && !dumb
+            }
+        }
+    }
+}
+
+pub fn new<'a, W: std::io::Write + 'a>(
+    w: W,
+    color: bool,
+    ansi: bool,
+    env: &crate::env::Env,
+) -> Box<dyn term::Terminal<Output = W> + 'a> {
+    if !color {
+        return Box::new(PlainTerminal { out: w });
+    }
+    if !ansi {
+        // TERM may be unset, or not have an entry (in containers, on serial
+        // consoles, etc), but nearly everything understands ansi escapes
+        if let Some(Ok(terminfo)) =
+            env.var("TERM").map(term::terminfo::TermInfo::from_name)
+        {
+            return Box::new(term::TerminfoTerminal::new_with_terminfo(
+                w, terminfo,
+            ));
+        }
+    }
+    Box::new(AnsiTerminal { out: w })
+}
+
+// how many palette colors the terminal from new() can draw
+pub fn num_colors(ansi: bool, env: &crate::env::Env) -> u32 {
+    if !ansi {
+        if let Some(Ok(terminfo)) =
+            env.var("TERM").map(term::terminfo::TermInfo::from_name)
+        {
+            return terminfo.numbers.get("colors").copied().unwrap_or(0);
+        }
+    }
+    256
+}
+
+// xterm style escapes, for when there is no terminfo database to ask.
+// assumes 256 colors, which is the common case these days.
+pub struct AnsiTerminal<W> {
+    out: W,
+}
+
+impl<W: std::io::Write> AnsiTerminal<W> {
+    fn sgr(&mut self, params: &str) -> term::Result<()> {
+        write!(self.out, "\x1b[{}m", params)?;
+        Ok(())
+    }
+
+    fn color(
+        &mut self,
+        color: term::color::Color,
+        background: bool,
+    ) -> term::Result<()> {
+        let (normal, bright, extended) = if background {
+            (40, 100, 48)
+        } else {
+            (30, 90, 38)
+        };
+        match color {
+            0..=7 => self.sgr(&(normal + color).to_string()),
+            8..=15 => self.sgr(&(bright + color - 8).to_string()),
+            16..=255 => self.sgr(&format!("{};5;{}", extended, color)),
+            _ => Err(term::Error::ColorOutOfRange),
+        }
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for AnsiTerminal<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl<W: std::io::Write> term::Terminal for AnsiTerminal<W> {
+    type Output = W;
+
+    fn fg(&mut self, color: term::color::Color) -> term::Result<()> {
+        self.color(color, false)
+    }
+
+    fn bg(&mut self, color: term::color::Color) -> term::Result<()> {
+        self.color(color, true)
+    }
+
+    fn attr(&mut self, attr: term::Attr) -> term::Result<()> {
+        match attr {
+            term::Attr::Bold => self.sgr("1"),
+            term::Attr::Dim => self.sgr("2"),
+            term::Attr::Italic(true) => self.sgr("3"),
+            term::Attr::Italic(false) => self.sgr("23"),
+            term::Attr::Underline(true) => self.sgr("4"),
+            term::Attr::Underline(false) => self.sgr("24"),
+            term::Attr::Blink => self.sgr("5"),
+            term::Attr::Standout(true) | term::Attr::Reverse => self.sgr("7"),
+            term::Attr::Standout(false) => self.sgr("27"),
+            term::Attr::Secure => self.sgr("8"),
+            term::Attr::ForegroundColor(color) => self.fg(color),
+            term::Attr::BackgroundColor(color) => self.bg(color),
+        }
+    }
+
+    fn supports_attr(&self, _attr: term::Attr) -> bool {
+        true
+    }
+
+    fn reset(&mut self) -> term::Result<()> {
+        self.sgr("0")
+    }
+
+    fn supports_reset(&self) -> bool {
+        true
+    }
+
+    fn supports_color(&self) -> bool {
+        true
+    }
+
+    fn cursor_up(&mut self) -> term::Result<()> {
+        write!(self.out, "\x1b[A")?;
+        Ok(())
+    }
+
+    fn delete_line(&mut self) -> term::Result<()> {
+        write!(self.out, "\x1b[2K")?;
+        Ok(())
+    }
+
+    fn carriage_return(&mut self) -> term::Result<()> {
+        write!(self.out, "\r")?;
+        Ok(())
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+// draws the same layout, without any styling. it can't reset styles, so
+// nothing tries to set them (or to wrap them in shell escapes).
+pub struct PlainTerminal<W> {
+    out: W,
+}
+
+impl<W: std::io::Write> std::io::Write for PlainTerminal<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl<W: std::io::Write> term::Terminal for PlainTerminal<W> {
+    type Output = W;
+
+    fn fg(&mut self, _color: term::color::Color) -> term::Result<()> {
+        Ok(())
+    }
+
+    fn bg(&mut self, _color: term::color::Color) -> term::Result<()> {
+        Ok(())
+    }
+
+    fn attr(&mut self, _attr: term::Attr) -> term::Result<()> {
+        Ok(())
+    }
+
+    fn supports_attr(&self, _attr: term::Attr) -> bool {
+        false
+    }
+
+    fn reset(&mut self) -> term::Result<()> {
+        Ok(())
+    }
+
+    fn supports_reset(&self) -> bool {
+        false
+    }
+
+    fn supports_color(&self) -> bool {
+        false
+    }
+
+    fn cursor_up(&mut self) -> term::Result<()> {
+        Err(term::Error::NotSupported)
+    }
+
+    fn delete_line(&mut self) -> term::Result<()> {
+        Err(term::Error::NotSupported)
+    }
+
+    fn carriage_return(&mut self) -> term::Result<()> {
+        Err(term::Error::NotSupported)
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    fn into_inner(self) -> W {
+        self.out
+    }
+}
@@ -0,0 +1,170 @@
+use crate::dirs;
+
+// the cached status is reused for as long as none of these (and none of the
+// tracked files, see tracked_stamp) have changed
+fn watched_paths(git: &git2::Repository) -> Vec<std::path::PathBuf> {
+    let dir = git.path();
+    let mut paths = vec![
+        dir.to_path_buf(),
+        dir.join("index"),
+        dir.join("HEAD"),
+        dir.join("FETCH_HEAD"),
+        dir.join("packed-refs"),
+        dir.join("refs/heads"),
+        dir.join("refs/remotes/origin"),
+        dir.join("refs/remotes/origin/HEAD"),
+    ];
+    if let Some(name) = git
+        .find_reference("HEAD")
+        .ok()
+        .and_then(|head| head.symbolic_target().map(String::from))
+    {
+        if let Some(branch) = name.strip_prefix("refs/heads/") {
+            paths.push(dir.join(&name));
+            paths.push(dir.join("refs/remotes/origin").join(branch));
+        }
+    }
+    if let Some(workdir) = git.workdir() {
+        paths.push(workdir.to_path_buf());
+    }
+    paths
+}
+
+// editing a tracked file doesn't touch the index or any directory, so the
+// stamp also covers every tracked file, along with the directories that
+// they are in (which change when new untracked files show up next to
+// them). this is still only a stat per file, which is much cheaper than
+// the walk over the untracked and ignored files that a status needs.
+fn tracked_stamp(git: &git2::Repository) -> Option<u64> {
+    use std::hash::{Hash as _, Hasher as _};
+    use std::os::unix::ffi::OsStrExt as _;
+
+    let workdir = git.workdir()?;
+    let index = git.index().ok()?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    let mut dirs = std::collections::HashSet::new();
+    for entry in index.iter() {
+        let path =
+            std::path::Path::new(std::ffi::OsStr::from_bytes(&entry.path));
+        entry.path.hash(&mut hasher);
+        file_stamp(&workdir.join(path)).hash(&mut hasher);
+        if let Some(parent) = path.parent() {
+            if dirs.insert(parent.to_path_buf()) {
+                file_stamp(&workdir.join(parent)).hash(&mut hasher);
+            }
+        }
+    }
+    Some(hasher.finish())
+}
+
+fn file_stamp(path: &std::path::Path) -> Option<(u128, u64)> {
+    let stat = std::fs::symlink_metadata(path).ok()?;
+    let modified = stat
+        .modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?;
+    Some((modified.as_nanos(), stat.len()))
+}
+
+fn stamp(git: &git2::Repository) -> String {
+    let mut stamp = watched_paths(git)
+        .iter()
+        .map(|path| {
+            std::fs::metadata(path)
+                .and_then(|stat| stat.modified())
+                .ok()
+                .and_then(|modified| {
+                    modified.duration_since(std::time::UNIX_EPOCH).ok()
+                })
+                .map(|since| since.as_nanos().to_string())
+                .unwrap_or_else(|| String::from("-"))
+        })
+        .collect::<Vec<_>>();
+    stamp.push(
+        tracked_stamp(git)
+            .map(|hash| format!("{:016x}", hash))
+            .unwrap_or_else(|| String::from("-")),
+    );
+    stamp.join(" ")
+}
+
+fn cache_file(git: &git2::Repository) -> Option<std::path::PathBuf> {
+    dirs::runtime_dir()
+        .map(|dir| dir.join("status").join(dirs::path_key(git.path())))
+}
+
+pub fn load(git: &git2::Repository) -> Option<String> {
+    let contents = std::fs::read_to_string(cache_file(git)?).ok()?;
+    let (cached_stamp, payload) = contents.split_once('\n')?;
+    if cached_stamp == stamp(git) {
+        Some(String::from(payload))
+    } else {
+        None
+    }
+}
+
+pub fn store(git: &git2::Repository, payload: &str) {
+    let file = if let Some(file) = cache_file(git) {
+        file
+    } else {
+        return;
+    };
+    if let Some(parent) = file.parent() {
+        if std::fs::create_dir_all(parent).is_err() {
+            return;
+        }
+    }
+
+    // write to a temporary file first so that concurrent prompts never see
+    // a partially written cache entry
+    let tmp = file.with_extension(format!("tmp{}", std::process::id()));
+    let contents = stamp(git) + "\n" + payload;
+    if std::fs::write(&tmp, contents).is_ok()
+        && std::fs::rename(&tmp, &file).is_err()
+    {
+        let _ = std::fs::remove_file(&tmp);
+    }
+}
+
+pub fn invalidate(git: &git2::Repository) -> std::io::Result<()> {
+    if let Some(file) = cache_file(git) {
+        match std::fs::remove_file(file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e)
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stamp() {
+        let dir = std::env::temp_dir()
+            .join(format!("fancy-prompt-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let git = git2::Repository::init(&dir).unwrap();
+        std::fs::create_dir_all(dir.join("dir")).unwrap();
+        std::fs::write(dir.join("dir/a"), "committed").unwrap();
+        let mut index = git.index().unwrap();
+        index.add_path(std::path::Path::new("dir/a")).unwrap();
+        index.write().unwrap();
+
+        // edits to tracked files and new files next to them are noticed,
+        // even though they don't touch the index or the top level
+        let before = stamp(&git);
+        assert_eq!(stamp(&git), before);
+        std::fs::write(dir.join("dir/a"), "edited!").unwrap();
+        let edited = stamp(&git);
+        assert_ne!(edited, before);
+        std::fs::write(dir.join("dir/b"), "untracked").unwrap();
+        assert_ne!(stamp(&git), edited);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+use std::os::unix::process::CommandExt as _;
+
+use crate::dirs;
+
+pub fn maybe_spawn(git: &git2::Repository, interval: std::time::Duration) {
+    let workdir = if let Some(workdir) = git.workdir() {
+        workdir
+    } else {
+        return;
+    };
+    if git.find_remote("origin").is_err() {
+        return;
+    }
+
+    let stamp = if let Some(dir) = dirs::cache_dir() {
+        dir.join("fetch").join(dirs::path_key(workdir))
+    } else {
+        return;
+    };
+    let last_attempt = std::fs::metadata(&stamp)
+        .and_then(|stat| stat.modified())
+        .ok();
+    if !is_due(last_attempt, std::time::SystemTime::now(), interval) {
+        return;
+    }
+
+    // record the attempt before spawning, so that prompts drawn while the
+    // fetch is still running don't start another one
+    if let Some(parent) = stamp.parent() {
+        if std::fs::create_dir_all(parent).is_err() {
+            return;
+        }
+    }
+    if std::fs::write(&stamp, b"").is_err() {
+        return;
+    }
+
+    let mut command = std::process::Command::new("git");
+    command
+        .args(["fetch", "--quiet", "origin"])
+        .current_dir(workdir)
+        .env("GIT_TERMINAL_PROMPT", "0");
+    if let Some(ssh_command) = batch_ssh_command(git) {
+        command.env("GIT_SSH_COMMAND", ssh_command);
+    }
+    let child = command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .process_group(0)
+        .spawn();
+    // the prompt doesn't wait for the fetch. a one-shot process exits first
+    // and the child is reparented, but the daemon has to reap it itself or
+    // it would collect zombies.
+    if let Ok(mut child) = child {
+        std::thread::spawn(move || child.wait());
+    }
+}
+
+// GIT_TERMINAL_PROMPT only covers git's own prompts. nobody is around to
+// answer ssh asking for a passphrase or about an unknown host key (and it
+// would get stopped for reading from the terminal in the background), so
+// make it fail instead. GIT_SSH (a program, not a command line) can't be
+// given options, so it's left alone.
+fn batch_ssh_command(git: &git2::Repository) -> Option<String> {
+    if std::env::var_os("GIT_SSH").is_some() {
+        return None;
+    }
+    let ssh_command = std::env::var("GIT_SSH_COMMAND")
+        .ok()
+        .or_else(|| git.config().ok()?.get_string("core.sshCommand").ok())
+        .unwrap_or_else(|| String::from("ssh"));
+    Some(format!("{} -o BatchMode=yes", ssh_command))
+}
+
+// a missing stamp (or one from the future, if the clock changed) means we
+// don't know when the last fetch was, so fetch now
+fn is_due(
+    last_attempt: Option<std::time::SystemTime>,
+    now: std::time::SystemTime,
+    interval: std::time::Duration,
+) -> bool {
+    last_attempt
+        .and_then(|last_attempt| now.duration_since(last_attempt).ok())
+        .map(|elapsed| elapsed >= interval)
+        .unwrap_or(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_due() {
+        let now =
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(1000);
+        let interval = std::time::Duration::from_secs(300);
+        let ago = |secs| Some(now - std::time::Duration::from_secs(secs));
+
+        assert!(is_due(None, now, interval));
+        assert!(!is_due(ago(0), now, interval));
+        assert!(!is_due(ago(299), now, interval));
+        assert!(is_due(ago(300), now, interval));
+        assert!(is_due(ago(900), now, interval));
+        assert!(is_due(
+            Some(now + std::time::Duration::from_secs(60)),
+            now,
+            interval
+        ));
+    }
+}
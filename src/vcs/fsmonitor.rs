@@ -0,0 +1,463 @@
+use std::io::{Read as _, Write as _};
+
+use crate::dirs;
+
+// this implements just enough of git's fsmonitor support (see
+// Documentation/config/core.txt and compat/fsmonitor in git.git) to avoid
+// rescanning the whole working tree when only a few paths have changed.
+
+enum Monitor {
+    Hook { command: String, version: i32 },
+    Daemon,
+}
+
+struct Response {
+    token: String,
+    // None means that the monitor couldn't tell us what changed, so
+    // everything needs to be rescanned
+    paths: Option<Vec<String>>,
+}
+
+struct State {
+    token: String,
+    stamp: String,
+    entries: std::collections::BTreeMap<String, u32>,
+}
+
+pub fn statuses(git: &git2::Repository) -> Option<Vec<git2::Status>> {
+    let monitor = monitor(git)?;
+    let workdir = git.workdir()?;
+
+    start_talking_about_time!("fsmonitor");
+    let statuses = monitored_statuses(git, &monitor, workdir);
+    stop_talking_about_time!();
+
+    statuses
+}
+
+fn monitored_statuses(
+    git: &git2::Repository,
+    monitor: &Monitor,
+    workdir: &std::path::Path,
+) -> Option<Vec<git2::Status>> {
+    let state = load(git).filter(|state| state.stamp == stamp(git));
+    talk_about_time!("load state");
+
+    let token = state
+        .as_ref()
+        .map(|state| state.token.clone())
+        .unwrap_or_else(|| initial_token(monitor));
+    let response = query(monitor, workdir, git.path(), &token);
+    talk_about_time!("query");
+    let response = response?;
+
+    let entries = match (state, response.paths) {
+        (Some(state), Some(paths)) => {
+            let entries = partial_scan(git, state.entries, &paths);
+            talk_about_time!("partial scan");
+            entries
+        }
+        _ => {
+            let entries = full_scan(git);
+            talk_about_time!("full scan");
+            entries
+        }
+    };
+    let entries = entries?;
+
+    let statuses = entries
+        .values()
+        .map(|bits| git2::Status::from_bits_truncate(*bits))
+        .collect();
+    store(
+        git,
+        &State {
+            token: response.token,
+            stamp: stamp(git),
+            entries,
+        },
+    );
+    talk_about_time!("store state");
+
+    Some(statuses)
+}
+
+fn monitor(git: &git2::Repository) -> Option<Monitor> {
+    let config = git.config().ok()?;
+    let value = config.get_string("core.fsmonitor").ok()?;
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(Monitor::Daemon),
+        "false" | "no" | "off" | "0" | "" => None,
+        _ => Some(Monitor::Hook {
+            command: value,
+            version: config.get_i32("core.fsmonitorHookVersion").unwrap_or(2),
+        }),
+    }
+}
+
+fn initial_token(monitor: &Monitor) -> String {
+    match monitor {
+        // version 1 hooks take a timestamp rather than an opaque token, and
+        // there is no point in asking about changes since the epoch
+        Monitor::Hook { version: 1, .. } => now_nanos(),
+        // the daemon replies with a fresh token (and a request for a full
+        // rescan) to any token it doesn't recognize
+        Monitor::Daemon => String::from("builtin:fake"),
+        Monitor::Hook { .. } => String::new(),
+    }
+}
+
+fn query(
+    monitor: &Monitor,
+    workdir: &std::path::Path,
+    git_dir: &std::path::Path,
+    token: &str,
+) -> Option<Response> {
+    match monitor {
+        Monitor::Hook {
+            command,
+            version: 1,
+        } => {
+            let next_token = now_nanos();
+            let output = run_hook(command, workdir, 1, token)?;
+            Some(Response {
+                token: next_token,
+                paths: parse_paths(output.split('\0')),
+            })
+        }
+        Monitor::Hook { command, .. } => {
+            parse_response(&run_hook(command, workdir, 2, token)?)
+        }
+        Monitor::Daemon => {
+            parse_response(&query_daemon(git_dir, token).ok()?)
+        }
+    }
+}
+
+fn run_hook(
+    command: &str,
+    workdir: &std::path::Path,
+    version: i32,
+    token: &str,
+) -> Option<String> {
+    // git runs the hook through the shell as well
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", command))
+        .arg(command)
+        .arg(version.to_string())
+        .arg(token)
+        .current_dir(workdir)
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if output.status.success() {
+        String::from_utf8(output.stdout).ok()
+    } else {
+        None
+    }
+}
+
+fn query_daemon(
+    git_dir: &std::path::Path,
+    token: &str,
+) -> std::io::Result<String> {
+    let mut socket = std::os::unix::net::UnixStream::connect(
+        git_dir.join("fsmonitor--daemon.ipc"),
+    )?;
+    let timeout = Some(std::time::Duration::from_millis(500));
+    socket.set_read_timeout(timeout)?;
+    socket.set_write_timeout(timeout)?;
+
+    // the daemon speaks git's pkt-line format: a request is a series of
+    // packets terminated by a flush packet, and so is the response
+    write!(socket, "{:04x}{}0000", token.len() + 4, token)?;
+
+    let mut response = vec![];
+    loop {
+        let mut len = [0; 4];
+        socket.read_exact(&mut len)?;
+        let len = std::str::from_utf8(&len)
+            .ok()
+            .and_then(|len| usize::from_str_radix(len, 16).ok())
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "invalid pkt-line length",
+                )
+            })?;
+        if len < 4 {
+            break;
+        }
+        let mut packet = vec![0; len - 4];
+        socket.read_exact(&mut packet)?;
+        response.extend(packet);
+    }
+
+    String::from_utf8(response)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+fn parse_response(response: &str) -> Option<Response> {
+    let mut parts = response.split('\0');
+    let token = parts.next().filter(|token| !token.is_empty())?;
+    Some(Response {
+        token: String::from(token),
+        paths: parse_paths(parts),
+    })
+}
+
+fn parse_paths<'a>(
+    paths: impl Iterator<Item = &'a str>,
+) -> Option<Vec<String>> {
+    let mut changed = vec![];
+    for path in paths.filter(|path| !path.is_empty()) {
+        if path == "/" {
+            return None;
+        }
+        changed.push(String::from(path.trim_end_matches('/')));
+    }
+    Some(changed)
+}
+
+fn full_scan(
+    git: &git2::Repository,
+) -> Option<std::collections::BTreeMap<String, u32>> {
+    let mut status_options = git2::StatusOptions::new();
+    status_options.include_untracked(true);
+    // see GitInfo::new
+    status_options.update_index(false);
+
+    let statuses = git.statuses(Some(&mut status_options)).ok()?;
+    Some(
+        statuses
+            .iter()
+            .filter_map(|entry| {
+                entry
+                    .path()
+                    .map(|path| (String::from(path), entry.status().bits()))
+            })
+            .collect(),
+    )
+}
+
+fn partial_scan(
+    git: &git2::Repository,
+    mut entries: std::collections::BTreeMap<String, u32>,
+    paths: &[String],
+) -> Option<std::collections::BTreeMap<String, u32>> {
+    if paths.is_empty() {
+        return Some(entries);
+    }
+
+    // untracked directories are reported as a single "dir/" entry rather
+    // than file by file, so a change inside one means rescanning all of it
+    let paths: std::collections::BTreeSet<&str> = paths
+        .iter()
+        .map(|path| {
+            entries
+                .keys()
+                .filter_map(|entry| entry.strip_suffix('/'))
+                .find(|dir| is_under(path, dir))
+                .unwrap_or(path)
+        })
+        .collect();
+    let paths: Vec<String> = paths.into_iter().map(String::from).collect();
+
+    entries.retain(|entry, _| {
+        let entry = entry.trim_end_matches('/');
+        !paths.iter().any(|path| is_under(entry, path))
+    });
+
+    let mut status_options = git2::StatusOptions::new();
+    status_options.include_untracked(true);
+    status_options.update_index(false);
+    // these are paths, not patterns, even if they contain glob characters
+    status_options.disable_pathspec_match(true);
+    for path in &paths {
+        status_options.pathspec(path);
+    }
+
+    let statuses = git.statuses(Some(&mut status_options)).ok()?;
+    for entry in statuses.iter() {
+        if let Some(path) = entry.path() {
+            entries.insert(String::from(path), entry.status().bits());
+        }
+    }
+    Some(entries)
+}
+
+// whether path is dir itself, or something inside of it
+fn is_under(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+// changes to the index or to HEAD can affect the status of paths that the
+// monitor won't report as changed, so those require a full rescan
+fn stamp(git: &git2::Repository) -> String {
+    let index = std::fs::metadata(git.path().join("index"))
+        .and_then(|stat| stat.modified())
+        .ok()
+        .and_then(|modified| {
+            modified.duration_since(std::time::UNIX_EPOCH).ok()
+        })
+        .map(|since| since.as_nanos().to_string())
+        .unwrap_or_else(|| String::from("-"));
+    let head = git
+        .head()
+        .ok()
+        .and_then(|head| head.target())
+        .map(|oid| oid.to_string())
+        .unwrap_or_else(|| String::from("-"));
+    index + " " + &head
+}
+
+fn now_nanos() -> String {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|since| since.as_nanos().to_string())
+        .unwrap_or_default()
+}
+
+fn state_file(git: &git2::Repository) -> Option<std::path::PathBuf> {
+    dirs::runtime_dir()
+        .map(|dir| dir.join("fsmonitor").join(dirs::path_key(git.path())))
+}
+
+fn load(git: &git2::Repository) -> Option<State> {
+    let contents = std::fs::read_to_string(state_file(git)?).ok()?;
+    let mut lines = contents.lines();
+    let token = String::from(lines.next()?);
+    let stamp = String::from(lines.next()?);
+    let mut entries = std::collections::BTreeMap::new();
+    for line in lines {
+        let (bits, path) = line.split_once('\t')?;
+        entries.insert(String::from(path), bits.parse().ok()?);
+    }
+    Some(State {
+        token,
+        stamp,
+        entries,
+    })
+}
+
+fn store(git: &git2::Repository, state: &State) {
+    let file = if let Some(file) = state_file(git) {
+        file
+    } else {
+        return;
+    };
+    if let Some(parent) = file.parent() {
+        if std::fs::create_dir_all(parent).is_err() {
+            return;
+        }
+    }
+
+    let mut contents = format!("{}\n{}\n", state.token, state.stamp);
+    for (path, bits) in &state.entries {
+        if path.contains('\n') {
+            // can't be represented, so just make sure we rescan next time
+            let _ = std::fs::remove_file(&file);
+            return;
+        }
+        contents += &format!("{}\t{}\n", bits, path);
+    }
+
+    let tmp = file.with_extension(format!("tmp{}", std::process::id()));
+    if std::fs::write(&tmp, contents).is_ok()
+        && std::fs::rename(&tmp, &file).is_err()
+    {
+        let _ = std::fs::remove_file(&tmp);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        let response = parse_response("c:123:4\0a.txt\0dir/\0").unwrap();
+        assert_eq!(response.token, "c:123:4");
+        assert_eq!(
+            response.paths,
+            Some(vec![String::from("a.txt"), String::from("dir")])
+        );
+
+        let response = parse_response("builtin:1:2\0/\0").unwrap();
+        assert_eq!(response.token, "builtin:1:2");
+        assert_eq!(response.paths, None);
+
+        let response = parse_response("c:1\0").unwrap();
+        assert_eq!(response.paths, Some(vec![]));
+
+        assert!(parse_response("").is_none());
+    }
+
+    #[test]
+    fn test_partial_scan() {
+        let dir = std::env::temp_dir()
+            .join(format!("fancy-prompt-fsmonitor-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let git = git2::Repository::init(&dir).unwrap();
+        let write = |path: &str, contents: &str| {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        let keys = |entries: &std::collections::BTreeMap<String, u32>| {
+            entries.keys().cloned().collect::<Vec<_>>()
+        };
+
+        for path in ["*.rs", "b.rs", "[a]", "a", "dir/c"] {
+            write(path, "committed");
+        }
+        let mut index = git.index().unwrap();
+        for path in ["*.rs", "b.rs", "[a]", "a", "dir/c"] {
+            index.add_path(std::path::Path::new(path)).unwrap();
+        }
+        index.write().unwrap();
+        let tree = git.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("doy", "doy@tozt.net").unwrap();
+        git.commit(Some("HEAD"), &sig, &sig, "initial", &tree, &[])
+            .unwrap();
+        let entries = full_scan(&git).unwrap();
+        assert!(entries.is_empty());
+
+        // only the reported paths are rescanned, and glob characters in
+        // them don't match anything else
+        write("*.rs", "changed");
+        write("b.rs", "changed");
+        write("[a]", "changed");
+        write("new/x", "untracked");
+        let entries = partial_scan(
+            &git,
+            entries,
+            &[
+                String::from("*.rs"),
+                String::from("[a]"),
+                String::from("new"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(keys(&entries), ["*.rs", "[a]", "new/"]);
+
+        // changes inside an untracked directory rescan the directory
+        write("new/y", "untracked");
+        let entries =
+            partial_scan(&git, entries, &[String::from("new/y")]).unwrap();
+        assert_eq!(keys(&entries), ["*.rs", "[a]", "new/"]);
+        std::fs::remove_dir_all(dir.join("new")).unwrap();
+        let entries =
+            partial_scan(&git, entries, &[String::from("new/x")]).unwrap();
+        assert_eq!(keys(&entries), ["*.rs", "[a]"]);
+
+        // and a full scan lines up with that, other than the unreported
+        // change
+        assert_eq!(keys(&full_scan(&git).unwrap()), ["*.rs", "[a]", "b.rs"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,22 @@
+mod cache;
+#[cfg(unix)]
+mod fetch;
+mod fsmonitor;
 mod git;
+#[cfg(target_os = "linux")]
+mod watch;
 
-#[derive(Debug, Copy, Clone)]
+#[cfg(target_os = "linux")]
+pub use self::watch::Watcher;
+
+#[derive(Debug, Copy, Clone, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum VcsType {
     Git,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ActiveOperation {
     None,
     Merge,
@@ -15,7 +26,7 @@ pub enum ActiveOperation {
     Rebase,
 }
 
-pub trait VcsInfo {
+pub trait VcsInfo: Send {
     fn vcs(&self) -> VcsType;
     fn has_modified_files(&self) -> bool;
     fn has_staged_files(&self) -> bool;
@@ -24,7 +35,12 @@ pub trait VcsInfo {
     fn active_operation(&self) -> ActiveOperation;
     fn branch(&self) -> Option<String>;
     fn remote_branch_diff(&self) -> Option<(usize, usize)>;
+    fn upstream(&self) -> Option<String>;
     fn default_branch(&self) -> Option<String>;
+    fn remote_url(&self) -> Option<String>;
+    fn head_commit_time(&self) -> Option<time::OffsetDateTime>;
+    fn last_fetch_time(&self) -> Option<time::OffsetDateTime>;
+    fn has_remotes(&self) -> bool;
 
     fn is_dirty(&self) -> bool {
         let diff = self.remote_branch_diff();
@@ -42,10 +58,67 @@ pub trait VcsInfo {
     }
 }
 
-pub fn detect() -> Option<Box<dyn VcsInfo>> {
-    if let Some(git) = git::detect() {
+// a plain copy of everything a VcsInfo knows, for --format json
+#[derive(Debug, serde::Serialize)]
+pub struct Snapshot {
+    pub vcs: VcsType,
+    pub modified_files: bool,
+    pub staged_files: bool,
+    pub new_files: bool,
+    pub commits: bool,
+    pub active_operation: ActiveOperation,
+    pub branch: Option<String>,
+    pub default_branch: Option<String>,
+    pub upstream: Option<String>,
+    pub remote_url: Option<String>,
+    pub ahead: Option<usize>,
+    pub behind: Option<usize>,
+    pub dirty: bool,
+    pub error: bool,
+    pub head_commit_time: Option<String>,
+    pub last_fetch_time: Option<String>,
+}
+
+pub fn snapshot(vcs_info: &dyn VcsInfo) -> Snapshot {
+    let format_time = |time: time::OffsetDateTime| {
+        time.format(&time::format_description::well_known::Rfc3339)
+            .ok()
+    };
+    let diff = vcs_info.remote_branch_diff();
+    Snapshot {
+        vcs: vcs_info.vcs(),
+        modified_files: vcs_info.has_modified_files(),
+        staged_files: vcs_info.has_staged_files(),
+        new_files: vcs_info.has_new_files(),
+        commits: vcs_info.has_commits(),
+        active_operation: vcs_info.active_operation(),
+        branch: vcs_info.branch(),
+        default_branch: vcs_info.default_branch(),
+        upstream: vcs_info.upstream(),
+        remote_url: vcs_info.remote_url(),
+        ahead: diff.map(|(local, _)| local),
+        behind: diff.map(|(_, remote)| remote),
+        dirty: vcs_info.is_dirty(),
+        error: vcs_info.is_error(),
+        head_commit_time: vcs_info.head_commit_time().and_then(format_time),
+        last_fetch_time: vcs_info.last_fetch_time().and_then(format_time),
+    }
+}
+
+pub struct DetectOptions {
+    pub background_fetch: Option<std::time::Duration>,
+    pub cache: bool,
+    pub env: crate::env::Env,
+}
+
+pub fn detect(opts: &DetectOptions) -> Option<Box<dyn VcsInfo>> {
+    if let Some(git) = git::detect(opts) {
         Some(git)
     } else {
         None
     }
 }
+
+pub fn invalidate_cache(env: &crate::env::Env) -> std::io::Result<()> {
+    git::invalidate_cache(env)
+}
@@ -0,0 +1,312 @@
+use super::git::GitInfo;
+
+const WATCH_MASK: inotify::WatchMask = inotify::WatchMask::MODIFY
+    .union(inotify::WatchMask::ATTRIB)
+    .union(inotify::WatchMask::CREATE)
+    .union(inotify::WatchMask::DELETE)
+    .union(inotify::WatchMask::DELETE_SELF)
+    .union(inotify::WatchMask::MOVE)
+    .union(inotify::WatchMask::MOVE_SELF);
+
+struct WatchedRepo {
+    git: git2::Repository,
+    // None when something changed since this was last computed
+    info: Option<GitInfo>,
+    // directories still being walked. changes in them can't be noticed
+    // yet, so the status isn't kept until this is back to zero.
+    walks: usize,
+}
+
+// walking a large work tree can take a while, so watches are added on a
+// separate thread rather than while a prompt is waiting
+struct Walk {
+    key: std::path::PathBuf,
+    root: std::path::PathBuf,
+    git_dir: std::path::PathBuf,
+    workdir: Option<std::path::PathBuf>,
+    // false inside the git directory, which is never ignored
+    ignores: bool,
+}
+
+enum Walked {
+    Watch(
+        inotify::WatchDescriptor,
+        std::path::PathBuf,
+        std::path::PathBuf,
+    ),
+    Done(std::path::PathBuf),
+}
+
+// keeps repositories open between prompts, and only recomputes their status
+// after inotify tells us that something in them has changed
+pub struct Watcher {
+    inotify: inotify::Inotify,
+    repos: std::collections::HashMap<std::path::PathBuf, WatchedRepo>,
+    watches: std::collections::HashMap<
+        inotify::WatchDescriptor,
+        (std::path::PathBuf, std::path::PathBuf),
+    >,
+    walks: std::sync::mpsc::Sender<Walk>,
+    walked: std::sync::mpsc::Receiver<Walked>,
+}
+
+impl Watcher {
+    pub fn new() -> std::io::Result<Watcher> {
+        let inotify = inotify::Inotify::init()?;
+        let (walks, walks_rx) = std::sync::mpsc::channel();
+        let (walked_tx, walked) = std::sync::mpsc::channel();
+        let watches = inotify.watches();
+        std::thread::spawn(move || walk_trees(watches, walks_rx, walked_tx));
+        Ok(Watcher {
+            inotify,
+            repos: std::collections::HashMap::new(),
+            watches: std::collections::HashMap::new(),
+            walks,
+            walked,
+        })
+    }
+
+    pub fn detect(
+        &mut self,
+        opts: &super::DetectOptions,
+    ) -> Option<Box<dyn super::VcsInfo>> {
+        start_talking_about_time!("watcher");
+        let info = self.watched_info(opts);
+        stop_talking_about_time!();
+
+        info.map(|info| Box::new(info) as Box<dyn super::VcsInfo>)
+    }
+
+    fn watched_info(
+        &mut self,
+        opts: &super::DetectOptions,
+    ) -> Option<GitInfo> {
+        self.process_events();
+        talk_about_time!("process events");
+
+        let key = repo_key(&opts.env)?;
+        if !self.repos.contains_key(&key) {
+            let git = super::git::open(&opts.env)?;
+            self.repos.insert(
+                key.clone(),
+                WatchedRepo {
+                    git,
+                    info: None,
+                    walks: 0,
+                },
+            );
+            self.watch_repo(&key);
+            talk_about_time!("open and watch repository");
+        }
+
+        let repo = self.repos.get(&key)?;
+        if let Some(interval) = opts.background_fetch {
+            super::fetch::maybe_spawn(&repo.git, interval);
+        }
+        talk_about_time!("background fetch");
+
+        if let Some(info) = &repo.info {
+            talk_about_time!("git info (unchanged)");
+            return Some(info.clone());
+        }
+
+        let info = GitInfo::new(&repo.git);
+        // computing the status can itself touch the index, so throw away
+        // the events that we caused before marking the info as current
+        self.process_events();
+        if let Some(repo) = self.repos.get_mut(&key) {
+            if repo.walks == 0 {
+                repo.info = Some(info.clone());
+            }
+        }
+        talk_about_time!("git info");
+
+        Some(info)
+    }
+
+    fn watch_repo(&mut self, key: &std::path::Path) {
+        let git_dir = if let Some(repo) = self.repos.get(key) {
+            repo.git.path().to_path_buf()
+        } else {
+            return;
+        };
+        // running out of watches just means that we won't notice changes
+        // in some directories, which is no worse than not running a daemon
+        if let Ok(wd) = self.inotify.watches().add(&git_dir, WATCH_MASK) {
+            self.watches
+                .insert(wd, (key.to_path_buf(), git_dir.clone()));
+        }
+        self.watch_tree(key, &git_dir.join("refs"));
+        let workdir = self
+            .repos
+            .get(key)
+            .and_then(|repo| repo.git.workdir())
+            .map(std::path::Path::to_path_buf);
+        if let Some(workdir) = workdir {
+            self.watch_tree(key, &workdir);
+        }
+    }
+
+    fn watch_tree(&mut self, key: &std::path::Path, root: &std::path::Path) {
+        let repo = if let Some(repo) = self.repos.get_mut(key) {
+            repo
+        } else {
+            return;
+        };
+        let walk = Walk {
+            key: key.to_path_buf(),
+            root: root.to_path_buf(),
+            git_dir: repo.git.path().to_path_buf(),
+            workdir: repo.git.workdir().map(std::path::Path::to_path_buf),
+            ignores: !root.starts_with(repo.git.path()),
+        };
+        // if the walking thread is gone, the count never drops back to zero
+        // and the status is just recomputed on every prompt
+        repo.walks += 1;
+        let _ = self.walks.send(walk);
+    }
+
+    fn process_walked(&mut self) {
+        while let Ok(walked) = self.walked.try_recv() {
+            match walked {
+                Walked::Watch(wd, key, dir) => {
+                    self.watches.insert(wd, (key, dir));
+                }
+                Walked::Done(key) => {
+                    if let Some(repo) = self.repos.get_mut(&key) {
+                        repo.walks -= 1;
+                    }
+                }
+            }
+        }
+    }
+
+    fn process_events(&mut self) {
+        // every watch that a finished walk added is known before its events
+        // are read, since the walk reports each watch before finishing
+        self.process_walked();
+        let mut buffer = [0; 4096];
+        loop {
+            let mut changed = vec![];
+            let mut new_dirs = vec![];
+            let mut overflowed = false;
+            match self.inotify.read_events(&mut buffer) {
+                Ok(events) => {
+                    for event in events {
+                        if event.mask.contains(inotify::EventMask::Q_OVERFLOW)
+                        {
+                            overflowed = true;
+                            continue;
+                        }
+                        if let Some((key, dir)) = self.watches.get(&event.wd)
+                        {
+                            changed.push(key.clone());
+                            if event.mask.contains(inotify::EventMask::ISDIR)
+                                && event.mask.intersects(
+                                    inotify::EventMask::CREATE
+                                        | inotify::EventMask::MOVED_TO,
+                                )
+                            {
+                                if let Some(name) =
+                                    event.name.filter(|name| *name != ".git")
+                                {
+                                    new_dirs
+                                        .push((key.clone(), dir.join(name)));
+                                }
+                            }
+                        }
+                        if event.mask.contains(inotify::EventMask::IGNORED) {
+                            self.watches.remove(&event.wd);
+                        }
+                    }
+                }
+                Err(_) => break,
+            }
+
+            if overflowed {
+                for repo in self.repos.values_mut() {
+                    repo.info = None;
+                }
+            }
+            for key in changed {
+                if let Some(repo) = self.repos.get_mut(&key) {
+                    repo.info = None;
+                }
+            }
+            for (key, dir) in new_dirs {
+                self.watch_tree(&key, &dir);
+            }
+        }
+    }
+}
+
+// this needs to be cheap, since the whole point is to avoid reopening the
+// repository on every prompt
+fn repo_key(env: &crate::env::Env) -> Option<std::path::PathBuf> {
+    let cwd = env.cwd()?;
+    if let Some(git_dir) = env.var_os("GIT_DIR") {
+        return Some(cwd.join(git_dir));
+    }
+    cwd.ancestors()
+        .map(|dir| dir.join(".git"))
+        .find(|git_dir| git_dir.exists())
+}
+
+fn walk_trees(
+    mut watches: inotify::Watches,
+    walks: std::sync::mpsc::Receiver<Walk>,
+    walked: std::sync::mpsc::Sender<Walked>,
+) {
+    let mut repos = std::collections::HashMap::new();
+    for walk in walks {
+        let git = repos.entry(walk.key.clone()).or_insert_with(|| {
+            let git = git2::Repository::open(&walk.git_dir).ok()?;
+            if let Some(workdir) = &walk.workdir {
+                git.set_workdir(workdir, false).ok()?;
+            }
+            Some(git)
+        });
+        for entry in walkdir::WalkDir::new(&walk.root)
+            .into_iter()
+            .filter_entry(|entry| {
+                entry.file_type().is_dir()
+                    && entry.file_name() != ".git"
+                    && !(walk.ignores
+                        && git
+                            .as_ref()
+                            .is_some_and(|git| is_ignored(git, entry.path())))
+            })
+            .filter_map(Result::ok)
+        {
+            if let Ok(wd) = watches.add(entry.path(), WATCH_MASK) {
+                let watch = Walked::Watch(
+                    wd,
+                    walk.key.clone(),
+                    entry.path().to_path_buf(),
+                );
+                if walked.send(watch).is_err() {
+                    return;
+                }
+            }
+        }
+        if walked.send(Walked::Done(walk.key)).is_err() {
+            return;
+        }
+    }
+}
+
+// build output and dependencies (target/, node_modules/, ...) change all the
+// time without changing the status, and can be huge
+fn is_ignored(git: &git2::Repository, path: &std::path::Path) -> bool {
+    let workdir = if let Some(workdir) = git.workdir() {
+        workdir
+    } else {
+        return false;
+    };
+    match path.strip_prefix(workdir) {
+        Ok(relative) if !relative.as_os_str().is_empty() => {
+            git.is_path_ignored(relative).unwrap_or(false)
+        }
+        _ => false,
+    }
+}
@@ -1,76 +1,298 @@
-#[cfg(feature = "verbose")]
-pub static mut STACK: Option<
-    Vec<(String, std::time::Instant, std::time::Instant)>,
-> = None;
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProfileFormat {
+    // indented lines on stderr as things happen
+    Text,
+    // chrome trace event json (for chrome://tracing, perfetto, etc)
+    Trace,
+    // a flat json list of spans
+    Summary,
+}
 
-#[cfg(feature = "verbose")]
-macro_rules! start_talking_about_time {
-    ($category:expr) => {
-        unsafe {
-            let stack =
-                crate::verbose::STACK.get_or_insert_with(|| Vec::new());
-            let len = stack.len();
-            let now = std::time::Instant::now();
-            let category = $category;
-            stack.push((String::from(category), now.clone(), now.clone()));
-            eprintln!("{}starting {}", " ".repeat(len), category);
+impl ProfileFormat {
+    pub fn from_str(format: &str) -> Option<Self> {
+        match format {
+            "text" => Some(ProfileFormat::Text),
+            "trace" => Some(ProfileFormat::Trace),
+            "summary" => Some(ProfileFormat::Summary),
+            _ => None,
         }
-    };
+    }
 }
 
-#[cfg(feature = "verbose")]
-macro_rules! talk_about_time {
-    ($what:expr) => {
-        unsafe {
-            let stack =
-                crate::verbose::STACK.get_or_insert_with(|| Vec::new());
-            let len = stack.len();
-            let last = stack.last_mut().unwrap();
-            let elapsed = last.1.elapsed();
-            eprintln!(
-                "{}{}: {} took {}.{:09}s",
-                " ".repeat(len),
-                last.0,
-                $what,
-                elapsed.as_secs(),
-                elapsed.subsec_nanos()
-            );
-            last.1 = std::time::Instant::now();
+struct Frame {
+    category: &'static str,
+    last: std::time::Instant,
+    start: std::time::Instant,
+}
+
+struct Span {
+    name: String,
+    path: Vec<String>,
+    thread: u64,
+    start: std::time::Instant,
+    end: std::time::Instant,
+}
+
+// chrome trace event format
+#[derive(serde::Serialize)]
+struct Trace<'a> {
+    #[serde(rename = "traceEvents")]
+    trace_events: Vec<TraceEvent<'a>>,
+}
+
+#[derive(serde::Serialize)]
+struct TraceEvent<'a> {
+    name: &'a str,
+    cat: &'static str,
+    ph: &'static str,
+    ts: u64,
+    dur: u64,
+    pid: u32,
+    tid: u64,
+    args: TraceArgs,
+}
+
+#[derive(serde::Serialize)]
+struct TraceArgs {
+    scope: String,
+}
+
+#[derive(serde::Serialize)]
+struct SummarySpan {
+    name: String,
+    thread: u64,
+    start_us: u64,
+    duration_us: u64,
+}
+
+// 0 means disabled, otherwise one more than the ProfileFormat index
+static FORMAT: std::sync::atomic::AtomicU8 =
+    std::sync::atomic::AtomicU8::new(0);
+static SPANS: std::sync::Mutex<Vec<Span>> = std::sync::Mutex::new(vec![]);
+static NEXT_THREAD: std::sync::atomic::AtomicU64 =
+    std::sync::atomic::AtomicU64::new(0);
+
+// slow data sources are collected on their own threads, so each thread
+// keeps its own stack
+thread_local! {
+    static STACK: std::cell::RefCell<Vec<Frame>> =
+        const { std::cell::RefCell::new(vec![]) };
+    static THREAD: u64 =
+        NEXT_THREAD.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+}
+
+const FORMATS: [ProfileFormat; 3] = [
+    ProfileFormat::Text,
+    ProfileFormat::Trace,
+    ProfileFormat::Summary,
+];
+
+// the stack is always maintained (it's cheap, and doesn't allocate past its
+// first frame), so that profiling can be enabled by command line options
+// after the outermost span has started
+pub fn enable(format: Option<ProfileFormat>) {
+    let format = format.or(if cfg!(feature = "verbose") {
+        Some(ProfileFormat::Text)
+    } else {
+        None
+    });
+    let idx = format
+        .and_then(|format| FORMATS.iter().position(|f| *f == format))
+        .map(|idx| idx as u8 + 1)
+        .unwrap_or(0);
+    FORMAT.store(idx, std::sync::atomic::Ordering::Relaxed);
+}
+
+pub fn format() -> Option<ProfileFormat> {
+    match FORMAT.load(std::sync::atomic::Ordering::Relaxed) {
+        0 => None,
+        idx => Some(FORMATS[usize::from(idx) - 1]),
+    }
+}
+
+pub fn enabled() -> bool {
+    format().is_some()
+}
+
+pub fn start(category: &'static str) {
+    let now = std::time::Instant::now();
+    STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        if format() == Some(ProfileFormat::Text) {
+            eprintln!("{}starting {}", " ".repeat(stack.len()), category);
         }
-    };
+        stack.push(Frame {
+            category,
+            last: now,
+            start: now,
+        });
+    })
 }
 
-#[cfg(feature = "verbose")]
-macro_rules! stop_talking_about_time {
-    () => {
-        unsafe {
-            let stack =
-                crate::verbose::STACK.get_or_insert_with(|| Vec::new());
-            let last = stack.pop().unwrap();
-            let elapsed = last.2.elapsed();
-            let len = stack.len();
-            eprintln!(
-                "{}ending {} (total {}.{:09}s)",
-                " ".repeat(len),
-                last.0,
-                elapsed.as_secs(),
-                elapsed.subsec_nanos()
-            );
+pub fn talk(what: &str) {
+    let now = std::time::Instant::now();
+    STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        let len = stack.len();
+        let format = format();
+        let path = match format {
+            Some(ProfileFormat::Trace) | Some(ProfileFormat::Summary) => {
+                path(&stack)
+            }
+            _ => vec![],
+        };
+        let last = if let Some(last) = stack.last_mut() {
+            last
+        } else {
+            return;
+        };
+        match format {
+            Some(ProfileFormat::Text) => {
+                let elapsed = now - last.last;
+                eprintln!(
+                    "{}{}: {} took {}.{:09}s",
+                    " ".repeat(len),
+                    last.category,
+                    what,
+                    elapsed.as_secs(),
+                    elapsed.subsec_nanos()
+                );
+            }
+            Some(_) => record(String::from(what), path, last.last, now),
+            None => {}
         }
+        last.last = std::time::Instant::now();
+    })
+}
+
+pub fn stop() {
+    let now = std::time::Instant::now();
+    STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        let last = if let Some(last) = stack.pop() {
+            last
+        } else {
+            return;
+        };
+        match format() {
+            Some(ProfileFormat::Text) => {
+                let elapsed = now - last.start;
+                eprintln!(
+                    "{}ending {} (total {}.{:09}s)",
+                    " ".repeat(stack.len()),
+                    last.category,
+                    elapsed.as_secs(),
+                    elapsed.subsec_nanos()
+                );
+            }
+            Some(_) => record(
+                String::from(last.category),
+                path(&stack),
+                last.start,
+                now,
+            ),
+            None => {}
+        }
+    })
+}
+
+// writes out the collected spans, for the formats that aren't written
+// incrementally. spans from threads that are still running (because they
+// missed their deadline) are not included.
+pub fn finish() {
+    let format = format();
+    let spans = std::mem::take(&mut *SPANS.lock().unwrap());
+    let origin = if let Some(origin) = spans.iter().map(|s| s.start).min() {
+        origin
+    } else {
+        return;
     };
+    let micros = |duration: std::time::Duration| duration.as_micros() as u64;
+
+    let out = match format {
+        Some(ProfileFormat::Trace) => {
+            let pid = std::process::id();
+            let events: Vec<_> = spans
+                .iter()
+                .map(|span| TraceEvent {
+                    name: &span.name,
+                    cat: "fancy-prompt",
+                    ph: "X",
+                    ts: micros(span.start - origin),
+                    dur: micros(span.end - span.start),
+                    pid,
+                    tid: span.thread,
+                    args: TraceArgs {
+                        scope: span.path.join("/"),
+                    },
+                })
+                .collect();
+            serde_json::to_string(&Trace {
+                trace_events: events,
+            })
+        }
+        Some(ProfileFormat::Summary) => {
+            let spans: Vec<_> = spans
+                .iter()
+                .map(|span| {
+                    let mut path = span.path.clone();
+                    path.push(span.name.clone());
+                    SummarySpan {
+                        name: path.join("/"),
+                        thread: span.thread,
+                        start_us: micros(span.start - origin),
+                        duration_us: micros(span.end - span.start),
+                    }
+                })
+                .collect();
+            serde_json::to_string(&spans)
+        }
+        Some(ProfileFormat::Text) | None => return,
+    };
+    eprintln!("{}", out.unwrap());
+}
+
+fn path(stack: &[Frame]) -> Vec<String> {
+    stack
+        .iter()
+        .map(|frame| String::from(frame.category))
+        .collect()
+}
+
+fn record(
+    name: String,
+    path: Vec<String>,
+    start: std::time::Instant,
+    end: std::time::Instant,
+) {
+    let thread = THREAD.with(|thread| *thread);
+    SPANS.lock().unwrap().push(Span {
+        name,
+        path,
+        thread,
+        start,
+        end,
+    });
 }
 
-#[cfg(not(feature = "verbose"))]
 macro_rules! start_talking_about_time {
-    ($e:expr) => {};
+    ($category:expr) => {
+        crate::verbose::start($category)
+    };
 }
 
-#[cfg(not(feature = "verbose"))]
+// the description is only evaluated when profiling is enabled, so it can
+// be formatted without costing anything otherwise
 macro_rules! talk_about_time {
-    ($e:expr) => {};
+    ($what:expr) => {
+        if crate::verbose::enabled() {
+            crate::verbose::talk(&$what)
+        }
+    };
 }
 
-#[cfg(not(feature = "verbose"))]
 macro_rules! stop_talking_about_time {
-    () => {};
+    () => {
+        crate::verbose::stop()
+    };
 }